@@ -1,9 +1,14 @@
 use raw_list::{Link, List, Node};
 
 use core::alloc::{GlobalAlloc, Layout};
+use core::hash::{Hash, Hasher};
+use core::panic::Location;
 use core::ptr::NonNull;
 use std::alloc::System;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 const PAGE_SIZE: usize = 4096;
 
@@ -12,6 +17,10 @@ const NODE_SIZE: usize = size_of::<Node<MetaData>>();
 // NODE_ALIGN = 8
 const NODE_ALIGN: usize = align_of::<Node<MetaData>>();
 
+// bucket i of the alignment histogram counts requests aligned to 1 << i, the last bucket also
+// takes everything above PAGE_SIZE
+pub const ALIGN_BUCKETS: usize = PAGE_SIZE.trailing_zeros() as usize + 1;
+
 // Time to steal from raw_rc
 //
 // - Node<MetaData> is a link in a doubly linked list containing allocation metadata
@@ -32,16 +41,32 @@ const NODE_ALIGN: usize = align_of::<Node<MetaData>>();
 // | `UserData`  | `layout.size`                                                                                                                                                     |
 //
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MetaData
 {
   // original base ptr, pre alignment
   pub base: NonNull<u8>,
 
-  // requested layout, no modifications
+  // requested layout, the size grows by slack when a leftover is folded in, see
+  // MetaAlloc::wasteful_allocations
   pub layout: Layout,
 }
 
+// what every allocation is recorded with next to its header in the live map, a header stays a
+// base and a layout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocLabel
+{
+  // handed out by alloc_with_id, 0 otherwise
+  pub id: u64,
+
+  // allocations this allocator had made before this one, lower is older
+  pub seq: u64,
+
+  // subsystem from alloc_tagged, 0 when untagged
+  pub tag: u16,
+}
+
 const PAGE_LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(PAGE_SIZE, PAGE_SIZE) };
 
 static FAKE_HEAP_SIZE: usize = PAGE_SIZE * 1024 * 1024;
@@ -50,6 +75,10 @@ struct FakeHeap
 {
   base: *mut u8,
   current_top: usize,
+
+  // stack of pages given back by release_page, each page stores the next one in its first word
+  released: *mut u8,
+  released_count: usize,
 }
 // only using when wrapped in a mutex
 unsafe impl Send for FakeHeap {}
@@ -69,6 +98,8 @@ impl Drop for FakeHeap
 static FAKE_HEAP: Mutex<FakeHeap> = Mutex::new(FakeHeap {
   current_top: 0,
   base: core::ptr::null_mut(),
+  released: core::ptr::null_mut(),
+  released_count: 0,
 });
 
 fn get_page() -> *mut u8
@@ -78,6 +109,17 @@ fn get_page() -> *mut u8
   {
     guard.base =
       unsafe { System.alloc(Layout::from_size_align(FAKE_HEAP_SIZE, PAGE_SIZE).unwrap()) };
+    // every page handed out is base + a multiple of PAGE_SIZE, so they are only aligned if base is
+    debug_assert!(guard.base.addr().is_multiple_of(PAGE_SIZE));
+  }
+
+  if !guard.released.is_null()
+  {
+    let pg = guard.released;
+    guard.released = unsafe { *pg.cast::<*mut u8>() };
+    guard.released_count -= 1;
+    debug_assert!(pg.addr().is_multiple_of(PAGE_SIZE));
+    return pg;
   }
 
   if guard.current_top >= FAKE_HEAP_SIZE
@@ -88,10 +130,38 @@ fn get_page() -> *mut u8
   unsafe {
     let ptr = guard.base.add(guard.current_top);
     guard.current_top += PAGE_SIZE;
+    debug_assert!(ptr.addr().is_multiple_of(PAGE_SIZE));
     ptr
   }
 }
 
+// which page of the fake heap ptr points into, None outside of the committed part
+pub fn page_index_of(ptr: *const u8) -> Option<usize>
+{
+  let guard = FAKE_HEAP.lock().expect("PAGE INDEX FAKE HEAP POISON");
+  ptr
+    .addr()
+    .checked_sub(guard.base.addr())
+    .filter(|offset| !guard.base.is_null() && *offset < guard.current_top)
+    .map(|offset| offset / PAGE_SIZE)
+}
+
+// pg has to be a whole page no free list references anymore
+fn release_page(pg: *mut u8)
+{
+  let mut guard = FAKE_HEAP.lock().expect("RELEASE PAGE FAKE HEAP POISON");
+  unsafe { pg.cast::<*mut u8>().write(guard.released) };
+  guard.released = pg;
+  guard.released_count += 1;
+}
+
+// bytes get_page can still hand out, released pages included
+fn heap_left() -> usize
+{
+  let guard = FAKE_HEAP.lock().expect("HEAP LEFT FAKE HEAP POISON");
+  FAKE_HEAP_SIZE - guard.current_top + guard.released_count * PAGE_SIZE
+}
+
 fn meta_write(meta: MetaData) -> NonNull<Node<MetaData>>
 {
   unsafe {
@@ -128,6 +198,7 @@ fn node_split(
     }
     else
     {
+      // remaining_size is below a header and its padding here, it becomes slack
       lhs.layout =
         Layout::from_size_align(lhs.layout.size() + remaining_size, lhs.layout.align()).unwrap();
       (meta_write(lhs), None)
@@ -135,6 +206,44 @@ fn node_split(
   }
 }
 
+// bytes of block that carving layout out of it costs beyond layout.size(), mirrors node_split. a
+// leftover big enough for its own block goes back to the free list and doesn't count
+fn fit_waste(block: &MetaData, layout: Layout) -> usize
+{
+  let used = MetaData::new(block.base, layout).total_size();
+  let rest = block.total_size() - used;
+  let rhs = unsafe { block.base.byte_add(used) };
+  if rest > MetaData::default_meta_offset(rhs) + NODE_SIZE
+  {
+    used - layout.size()
+  }
+  else
+  {
+    block.total_size() - layout.size()
+  }
+}
+
+// (start, end) addresses of the whole pages inside block that trimming can hand back to the fake
+// heap, None without any. a leftover too small for a header has to stay part of a trimmed page
+fn trim_range(block: &MetaData) -> Option<(usize, usize)>
+{
+  let start = block.base.addr().get();
+  let end = start + block.total_size();
+  let mut pages_start = start.next_multiple_of(PAGE_SIZE);
+  let mut pages_end = end - end % PAGE_SIZE;
+
+  if pages_start > start
+    && pages_start - start < MetaData::default_meta_offset(block.base) + NODE_SIZE
+  {
+    pages_start += PAGE_SIZE;
+  }
+  if end > pages_end && end - pages_end < NODE_SIZE
+  {
+    pages_end = pages_end.saturating_sub(PAGE_SIZE);
+  }
+  (pages_end > pages_start).then_some((pages_start, pages_end))
+}
+
 fn raw_to_existing_node(ptr: *mut u8) -> NonNull<Node<MetaData>>
 {
   unsafe {
@@ -154,6 +263,29 @@ fn node_to_data_ptr(node: NonNull<Node<MetaData>>) -> *mut u8
   unsafe { (*node.as_ptr()).elem().data_location().as_ptr() }
 }
 
+// rewrites the header of a block that is about to be freed as a blank NODE_ALIGN block of the same
+// total size. otherwise the over aligned layout of the last allocation sticks to the free block and
+// survives merges, leaving its header (and usable_size) offset as if it were still allocated
+fn free_node(node: NonNull<Node<MetaData>>) -> NonNull<Node<MetaData>>
+{
+  let meta = unsafe { (*node.as_ptr()).elem().clone() };
+  let blank =
+    MetaData::new_blank(meta.base, meta.total_size()).expect("freed block lost its header");
+  if blank == meta
+  {
+    node
+  }
+  else
+  {
+    meta_write(blank)
+  }
+}
+
+// only physical adjacency is checked, that is enough even across page boundaries: every page
+// is carved out of the one contiguous FAKE_HEAP block and a page only reaches a free list once
+// it has been committed to that allocator, so both neighbours are always owned by the same list.
+// two blocks of different segments (see MetaAlloc::segments) are never adjacent, a gap of foreign
+// pages sits between them
 fn merge_right(link: Link<MetaData>) -> bool
 {
   unsafe {
@@ -193,309 +325,5005 @@ fn merge_right(link: Link<MetaData>) -> bool
   }
 }
 
-struct MetaAllocInner
+// what MetaAllocInner needs from the container holding its free blocks. a block is its header, the
+// Node<MetaData> written into the managed pages, and blocks stay in address order. List<MetaData>
+// is the intrusive list every allocator uses
+trait FreeList
 {
-  list: List<MetaData>,
-}
+  // links node in at its address. with merge it absorbs the blocks it touches on either side and
+  // returns how many, 0 otherwise
+  fn insert_sorted(&mut self, node: NonNull<Node<MetaData>>, merge: bool) -> usize;
 
-pub struct MetaAlloc
-{
-  tex: Mutex<MetaAllocInner>,
-}
-unsafe impl Send for MetaAlloc {}
-unsafe impl Sync for MetaAlloc {}
+  // unlinks the first block pick accepts, pick sees them in address order
+  fn remove(&mut self, pick: impl FnMut(&MetaData) -> bool) -> Option<NonNull<Node<MetaData>>>;
 
-impl MetaAlloc
-{
-  pub const fn new() -> Self
-  {
-    Self {
-      tex: Mutex::new(MetaAllocInner { list: List::new() }),
-    }
-  }
+  // every block, lowest address first
+  fn iterate(&self) -> impl Iterator<Item = NonNull<Node<MetaData>>>;
+
+  // folds every run of touching blocks into its first block, returns the merges done and the
+  // total size of the blocks that got absorbed
+  fn merge_neighbors(&mut self) -> (usize, usize);
+
+  // unlinks every block and links sorted in instead, sorted has to be in address order already
+  fn replace(&mut self, sorted: impl IntoIterator<Item = NonNull<Node<MetaData>>>);
 }
 
-impl MetaAllocInner
+impl FreeList for List<MetaData>
 {
-  unsafe fn try_add_page(&mut self) -> bool
-  {
-    let pg = get_page();
-    if pg.is_null()
-    {
-      false
-    }
-    else
-    {
-      let meta = MetaData::new_blank(NonNull::new(pg).unwrap(), PAGE_SIZE);
-      let node = meta_write(meta);
-      unsafe {
-        self.dealloc(node_to_data_ptr(node), PAGE_LAYOUT);
-      };
-      true
-    }
-  }
-
-  unsafe fn alloc(&mut self, layout: Layout) -> *mut u8
+  fn insert_sorted(&mut self, node: NonNull<Node<MetaData>>, merge: bool) -> usize
   {
-    if self.list.empty()
-    {
-      if !unsafe { self.try_add_page() }
-      {
-        return core::ptr::null_mut();
-      }
-    }
-
-    let mut cursor = self.list.cursor_mut();
+    let mut merges = 0;
+    let mut cursor = self.cursor_mut();
     cursor.move_next();
     while let Some(current) = cursor.current_value()
     {
-      if current.check_compatible(&layout)
+      if unsafe { *current > *(*node.as_ptr()).elem() }
       {
-        let node = cursor.remove().unwrap();
+        cursor.insert_before(node);
+        if !merge
+        {
+          return 0;
+        }
+        cursor.move_prev();
+
+        if merge_right(cursor.current_link())
+        {
+          cursor.move_next();
+          cursor.remove();
+          cursor.move_prev();
+          merges += 1;
+        }
 
-        let (ret_node, remaining) = node_split(node, layout);
-        if let Some(rem) = remaining
+        cursor.move_prev();
+        if merge_right(cursor.current_link())
         {
-          unsafe { self.dealloc(node_to_data_ptr(rem), (*rem.as_ptr()).elem().layout) };
+          cursor.move_next();
+          cursor.remove();
+          merges += 1;
         }
-        return node_to_data_ptr(ret_node);
+
+        return merges;
       }
       cursor.move_next();
     }
 
-    if !unsafe { self.try_add_page() }
-    {
-      core::ptr::null_mut()
-    }
-    else
+    // node goes last, so the old back is the only block it can merge with
+    let old_back = self.peek_back();
+    self.push_back(node);
+    if merge && merge_right(old_back)
     {
-      unsafe { self.alloc(layout) }
+      self.pop_back();
+      merges += 1;
     }
+    merges
   }
 
-  unsafe fn dealloc(&mut self, ptr: *mut u8, _layout: Layout)
+  fn remove(&mut self, mut pick: impl FnMut(&MetaData) -> bool) -> Option<NonNull<Node<MetaData>>>
   {
-    let node = raw_to_existing_node(ptr);
-    if self.list.empty()
+    let mut cursor = self.cursor_mut();
+    cursor.move_next();
+    while let Some(current) = cursor.current_value()
     {
-      self.list.push_front(node);
-      return;
-    }
-
-    unsafe {
-      let mut cursor = self.list.cursor_mut();
-      cursor.move_next();
-      while let Some(current) = cursor.current_value()
+      if pick(current)
       {
-        if *current > *(*node.as_ptr()).elem()
-        {
-          cursor.insert_before(node);
-          cursor.move_prev();
+        return cursor.remove();
+      }
+      cursor.move_next();
+    }
+    None
+  }
 
-          if merge_right(cursor.current_link())
-          {
-            cursor.move_next();
-            cursor.remove();
-            cursor.move_prev();
-          }
+  fn iterate(&self) -> impl Iterator<Item = NonNull<Node<MetaData>>>
+  {
+    core::iter::successors(self.peek_front(), |node| unsafe {
+      (*node.as_ptr()).next_node()
+    })
+  }
 
-          cursor.move_prev();
-          if merge_right(cursor.current_link())
-          {
-            cursor.move_next();
-            cursor.remove();
-          }
+  fn merge_neighbors(&mut self) -> (usize, usize)
+  {
+    let (mut merges, mut absorbed) = (0, 0);
+    let mut cursor = self.cursor_mut();
+    cursor.move_next();
+    while let Some(p_node) = cursor.current_link()
+    {
+      let right_size = unsafe {
+        (*p_node.as_ptr())
+          .next_node()
+          .map_or(0, |right| (*right.as_ptr()).elem().total_size())
+      };
 
-          return;
-        }
+      if merge_right(Some(p_node))
+      {
+        merges += 1;
+        absorbed += right_size;
         cursor.move_next();
+        cursor.remove();
+        cursor.move_prev();
       }
-
-      self.list.push_back(node);
-      let mut end_cursor = self.list.cursor_mut();
-      end_cursor.move_prev();
-      end_cursor.move_prev();
-      if merge_right(end_cursor.current_link())
+      else
       {
-        self.list.pop_back();
+        cursor.move_next();
       }
     }
+    (merges, absorbed)
+  }
+
+  fn replace(&mut self, sorted: impl IntoIterator<Item = NonNull<Node<MetaData>>>)
+  {
+    while self.pop_back().is_some()
+    {}
+    for node in sorted
+    {
+      self.push_back(node);
+    }
   }
 }
 
-unsafe impl GlobalAlloc for MetaAlloc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AllocStats
 {
-  unsafe fn alloc(&self, layout: Layout) -> *mut u8
+  // successful allocations handed out
+  pub allocations: usize,
+  pub deallocations: usize,
+
+  // usable bytes of every live allocation
+  pub bytes_in_use: usize,
+  pub pages_committed: usize,
+  // pages handed back to the fake heap to stay under max_free_bytes
+  pub pages_released: usize,
+  // free list nodes looked at while searching for a fit
+  pub free_list_steps: usize,
+  // times alloc, dealloc or dealloc_batch took the allocator lock
+  pub lock_acquisitions: usize,
+  // freed blocks that merged with a free neighbour, a block merging on both sides counts twice.
+  // every merge of a coalesce_free pass counts as one too
+  pub merges_performed: usize,
+}
+
+impl AllocStats
+{
+  const fn new() -> Self
   {
-    unsafe {
-      self
-        .tex
-        .lock()
-        .expect("Meta alloc tex poison alloc")
-        .alloc(layout)
+    Self {
+      allocations: 0,
+      deallocations: 0,
+      bytes_in_use: 0,
+      pages_committed: 0,
+      pages_released: 0,
+      free_list_steps: 0,
+      lock_acquisitions: 0,
+      merges_performed: 0,
     }
   }
 
-  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+  // per field self - earlier, bytes_in_use wraps if it shrank in between
+  pub fn delta(&self, earlier: &AllocStats) -> AllocStats
   {
-    unsafe {
-      self
-        .tex
-        .lock()
-        .expect("Meta alloc tex poison dealloc")
-        .dealloc(ptr, layout)
-    };
+    AllocStats {
+      allocations: self.allocations.wrapping_sub(earlier.allocations),
+      deallocations: self.deallocations.wrapping_sub(earlier.deallocations),
+      bytes_in_use: self.bytes_in_use.wrapping_sub(earlier.bytes_in_use),
+      pages_committed: self.pages_committed.wrapping_sub(earlier.pages_committed),
+      pages_released: self.pages_released.wrapping_sub(earlier.pages_released),
+      free_list_steps: self.free_list_steps.wrapping_sub(earlier.free_list_steps),
+      lock_acquisitions: self.lock_acquisitions.wrapping_sub(earlier.lock_acquisitions),
+      merges_performed: self.merges_performed.wrapping_sub(earlier.merges_performed),
+    }
   }
 }
 
-impl MetaData
+// every counter the allocator keeps, taken under one lock so it is cheap to grab around a
+// benchmark and diff afterwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot
 {
-  pub fn data_location(&self) -> NonNull<u8>
+  pub stats: AllocStats,
+  pub align_counts: [usize; ALIGN_BUCKETS],
+}
+
+impl Snapshot
+{
+  pub fn delta(&self, earlier: &Snapshot) -> Snapshot
   {
-    // base + padding + metadata size
-    unsafe { self.base.byte_add(self.extra_size()) }
+    Snapshot {
+      stats: self.stats.delta(&earlier.stats),
+      align_counts: core::array::from_fn(|i| {
+        self.align_counts[i].wrapping_sub(earlier.align_counts[i])
+      }),
+    }
   }
+}
 
-  pub fn meta_location(&self) -> NonNull<Node<MetaData>>
+// the block layout of one allocator at some point, see MetaAlloc::checkpoint
+#[derive(Debug, Clone)]
+pub struct Checkpoint
+{
+  free: Vec<MetaData>,
+  live: Vec<(MetaData, AllocLabel)>,
+  locations: BTreeMap<usize, &'static Location<'static>>,
+  slack: BTreeMap<usize, usize>,
+}
+
+// every page any of metas touches, sorted by address
+fn pages_of<'a>(metas: impl Iterator<Item = &'a MetaData>) -> Vec<*mut u8>
+{
+  let mut pages = Vec::new();
+  for meta in metas
   {
-    // data_location - node size
-    unsafe { self.data_location().byte_sub(NODE_SIZE).cast() }
+    let start = meta.base.addr().get();
+    let end = start + meta.total_size();
+    let first = start - start % PAGE_SIZE;
+    for page in (first..end).step_by(PAGE_SIZE)
+    {
+      pages.push(meta.base.as_ptr().with_addr(page));
+    }
   }
+  pages.sort_by_key(|page| page.addr());
+  pages.dedup_by_key(|page| page.addr());
+  pages
+}
 
-  pub fn extra_size(&self) -> usize
-  {
-    // bytes to align to `self.layout.align().max(NODE_ALIGN)` so the rest of the calculation is correct
-    let align = self.base.align_offset(self.layout.align().max(NODE_ALIGN));
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BlockInfo
+{
+  #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_addr"))]
+  pub base: NonNull<u8>,
+  // whole block, padding and header included
+  pub size: usize,
+  pub allocated: bool,
+  #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_layout"))]
+  pub layout: Layout,
+}
 
-    NODE_SIZE.next_multiple_of(self.layout.align()) + align
-  }
+// pointers only make sense in this process, a report keeps the address
+#[cfg(feature = "serde")]
+fn serialize_addr<S: serde::Serializer>(ptr: &NonNull<u8>, s: S) -> Result<S::Ok, S::Error>
+{
+  s.serialize_u64(ptr.addr().get() as u64)
+}
 
-  pub fn default_meta_offset(base: NonNull<u8>) -> usize
+#[cfg(feature = "serde")]
+fn serialize_layout<S: serde::Serializer>(layout: &Layout, s: S) -> Result<S::Ok, S::Error>
+{
+  serde::Serialize::serialize(&(layout.size(), layout.align()), s)
+}
+
+// everything needed to look at an allocator's heap offline, see MetaAlloc::report
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HeapReport
+{
+  // walk_heap, in address order
+  pub blocks: Vec<BlockInfo>,
+  pub stats: AllocStats,
+  pub page_size: usize,
+}
+
+impl BlockInfo
+{
+  fn new(meta: &MetaData, allocated: bool) -> Self
   {
-    let temp_meta = MetaData::new(
-      base.clone(),
-      Layout::from_size_align(0, NODE_ALIGN).unwrap(),
-    );
-    unsafe { temp_meta.meta_location().byte_offset_from_unsigned(base) }
+    Self {
+      base: meta.base,
+      size: meta.total_size(),
+      allocated,
+      layout: meta.layout,
+    }
   }
+}
 
-  pub fn usable_size(&self) -> usize
-  {
-    let node_padding = Self::default_meta_offset(self.base);
-    let current_padding = unsafe { self.meta_location().byte_offset_from_unsigned(self.base) };
+fn list_metas(list: &impl FreeList) -> Vec<MetaData>
+{
+  list
+    .iterate()
+    .map(|node| unsafe { (*node.as_ptr()).elem().clone() })
+    .collect()
+}
 
-    self.layout.size() + (current_padding - node_padding)
+// get_page for one allocator, null once it holds max_pages pages
+fn commit_page(stats: &mut AllocStats, max_pages: Option<usize>) -> *mut u8
+{
+  if max_pages.is_some_and(|max| stats.pages_committed - stats.pages_released >= max)
+  {
+    return core::ptr::null_mut();
   }
 
-  pub fn total_size(&self) -> usize
+  let pg = get_page();
+  if !pg.is_null()
   {
-    self.layout.size() + self.extra_size()
+    stats.pages_committed += 1;
   }
+  pg
+}
 
-  pub fn check_compatible(&self, lay: &Layout) -> bool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OomAction
+{
+  // the handler freed something, try the allocation once more
+  Retry,
+  Fail,
+}
+
+// why alloc_checked failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError
+{
+  // align is not a power of two
+  BadAlign,
+  // size rounded up to align doesn't fit in isize
+  BadLayout,
+  OutOfMemory,
+}
+
+impl core::fmt::Display for AllocError
+{
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result
   {
-    if lay.align() > self.layout.align()
+    f.write_str(match self
     {
-      let new_meta = Self::new(self.base.clone(), lay.clone());
+      AllocError::BadAlign => "alignment is not a power of two",
+      AllocError::BadLayout => "size rounded up to the alignment overflows isize",
+      AllocError::OutOfMemory => "allocator is out of memory",
+    })
+  }
+}
+
+impl std::error::Error for AllocError {}
 
-      self
-        .total_size()
-        .checked_sub(new_meta.extra_size())
-        .map_or(false, |x| x >= lay.size())
+// which free block alloc carves, see MetaAlloc::set_fit_strategy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitStrategy
+{
+  // the lowest addressed block that fits
+  FirstFit,
+  // the block that fits with the fewest bytes of padding, header and folded leftover on top of the
+  // requested size, ties go to the lower address. always scans the whole free list
+  MinWaste,
+}
+
+// fixed size cells carved out of whole pages, free cells form a stack through their first word
+struct Slab
+{
+  layout: Layout,
+  cell_size: usize,
+  free: *mut u8,
+}
+
+impl Slab
+{
+  fn alloc(
+    &mut self,
+    layout: Layout,
+    stats: &mut AllocStats,
+    max_pages: Option<usize>,
+  ) -> *mut u8
+  {
+    if layout != self.layout
+    {
+      return core::ptr::null_mut();
     }
-    else
+
+    if self.free.is_null()
     {
-      self.usable_size() >= lay.size()
+      let pg = commit_page(stats, max_pages);
+      if pg.is_null()
+      {
+        return core::ptr::null_mut();
+      }
+
+      for i in (0..PAGE_SIZE / self.cell_size).rev()
+      {
+        unsafe { self.dealloc(pg.add(i * self.cell_size)) };
+      }
     }
+
+    let cell = self.free;
+    self.free = unsafe { *cell.cast::<*mut u8>() };
+    cell
   }
 
-  pub fn new(base: NonNull<u8>, layout: Layout) -> Self
+  unsafe fn dealloc(&mut self, ptr: *mut u8)
   {
-    Self { base, layout }
+    unsafe { ptr.cast::<*mut u8>().write(self.free) };
+    self.free = ptr;
   }
+}
 
-  // used to create a blank unallocated node with the correct sizing from a new page
-  pub fn new_blank(base: NonNull<u8>, size: usize) -> Self
-  {
-    let padding = Self::default_meta_offset(base);
-    let total_removed = padding + NODE_SIZE;
-    let ret = Self {
-      base,
-      layout: Layout::from_size_align(size - total_removed, NODE_ALIGN).unwrap(),
-    };
+// the node and label of a live allocation, see MetaAllocInner::live
+type LiveEntry = (NonNull<Node<MetaData>>, AllocLabel);
 
-    ret
-  }
+struct MetaAllocInner
+{
+  // only touched through FreeList
+  list: List<MetaData>,
+  // header and label of every allocation handed out and not yet freed, by data address
+  live: BTreeMap<usize, LiveEntry>,
+  stats: AllocStats,
+  align_counts: [usize; ALIGN_BUCKETS],
+  max_free_bytes: Option<usize>,
+  preserve_large: Option<usize>,
+  slab: Option<Slab>,
+  max_pages: Option<usize>,
+  oom_handler: Option<fn(Layout) -> OomAction>,
+  alloc_fill: Option<u8>,
+  fit_strategy: FitStrategy,
+  // alignment alloc_cache_aligned uses
+  cache_line: usize,
+  // bytes skipped at the start of the next committed page, see MetaAlloc::with_region_align
+  region_offset: usize,
+  // call sites of the live allocations made through alloc_located, by data address
+  locations: BTreeMap<usize, &'static Location<'static>>,
+  // bytes node_split folded into a live allocation past what its caller asked for, by data
+  // address. allocations without slack have no entry
+  slack: BTreeMap<usize, usize>,
+  // allocations left before every alloc fails, see MetaAlloc::set_fail_after
+  fail_after: Option<usize>,
+  // free list length above which the unmerged frees get coalesced, see
+  // MetaAlloc::with_lazy_coalesce
+  lazy_coalesce: Option<usize>,
+
+  // free list steps summed over the alloc calls made while scan timing is on
+  scan_timing: bool,
+  scan_sum: usize,
+  scan_count: usize,
+
+  // the block freed last and the layout its caller had asked for, which the header layout only
+  // holds together with the slack. kept off the free list while cache_last_freed is on so an alloc
+  // of exactly that layout takes it back without a scan
+  cache_last_freed: bool,
+  last_freed: Option<(NonNull<Node<MetaData>>, Layout)>,
 }
 
-impl PartialOrd for MetaData
+pub struct MetaAlloc
 {
-  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering>
+  tex: Mutex<MetaAllocInner>,
+  next_id: AtomicU64,
+  frozen: AtomicBool,
+}
+unsafe impl Send for MetaAlloc {}
+unsafe impl Sync for MetaAlloc {}
+
+impl Default for MetaAlloc
+{
+  fn default() -> Self
   {
-    self.base.partial_cmp(&other.base)
+    Self::new()
   }
 }
 
-#[cfg(test)]
-mod meta_tests
+impl MetaAlloc
 {
-  const ALLOC_COUNT: usize = 1000;
+  pub const fn new() -> Self
+  {
+    Self {
+      tex: Mutex::new(MetaAllocInner::new()),
+      next_id: AtomicU64::new(1),
+      frozen: AtomicBool::new(false),
+    }
+  }
+
+  // an allocator that only serves Layout { object_size, object_align } out of fixed size cells,
+  // every other layout gets null. cells carry no header, so ids, live tracking and the free
+  // list checks don't apply to it
+  pub fn slab(object_size: usize, object_align: usize) -> Self
+  {
+    let layout = Layout::from_size_align(object_size, object_align).unwrap();
+    let cell_size = object_size
+      .max(size_of::<*mut u8>())
+      .next_multiple_of(object_align.max(align_of::<*mut u8>()));
+    assert!(
+      cell_size <= PAGE_SIZE,
+      "slab cells of {} bytes don't fit a page",
+      cell_size
+    );
+
+    let ret = Self::new();
+    ret.tex.lock().expect("Meta alloc tex poison slab").slab = Some(Slab {
+      layout,
+      cell_size,
+      free: core::ptr::null_mut(),
+    });
+    ret
+  }
+
+  // every pointer alloc returns is aligned to at least this, whatever the layout asks for: the
+  // node in front of the data needs it. slab cells hold a pointer, which needs no more
+  pub const fn min_guaranteed_align() -> usize
+  {
+    NODE_ALIGN
+  }
+
+  // every block is handed out with its usable region set to byte, so reads of memory the caller
+  // never wrote stand out. alloc_zeroed still zeroes
+  pub fn with_alloc_fill(byte: u8) -> Self
+  {
+    let ret = Self::new();
+    ret.tex.lock().expect("Meta alloc tex poison alloc fill").alloc_fill = Some(byte);
+    ret
+  }
+
+  // dealloc only inserts the freed block in address order and leaves its neighbours alone. once
+  // the free list holds more than threshold blocks one coalesce_free pass merges all of them, and
+  // an alloc that found no fit runs one before committing a page. until then validate and
+  // assert_healthy accept adjacent free blocks
+  pub fn with_lazy_coalesce(threshold: usize) -> Self
+  {
+    let ret = Self::new();
+    ret.tex.lock().expect("Meta alloc tex poison lazy coalesce").lazy_coalesce = Some(threshold);
+    ret
+  }
+
+  // pages are PAGE_SIZE aligned, which hides alignment bugs. the first page this allocator
+  // commits starts its heap align bytes in, so the heap base is aligned to align and nothing more.
+  // later pages are whole and merge onto it as usual
+  #[cfg(test)]
+  pub fn with_region_align(align: usize) -> Self
+  {
+    assert!(
+      align.is_power_of_two() && align < PAGE_SIZE,
+      "region align {} has to be a power of two below PAGE_SIZE",
+      align
+    );
+    let ret = Self::new();
+    ret.tex.lock().expect("Meta alloc tex poison region align").region_offset = align;
+    ret
+  }
+
+  pub fn stats(&self) -> AllocStats
+  {
+    self.tex.lock().expect("Meta alloc tex poison stats").stats
+  }
+
+  pub fn snapshot(&self) -> Snapshot
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison snapshot");
+    Snapshot {
+      stats: inner.stats,
+      align_counts: inner.align_counts,
+    }
+  }
+
+  // usable bytes of the live allocations beyond what their callers asked for. external
+  // fragmentation shows in free_gaps and fragmentation_by_class instead
+  pub fn internal_fragmentation(&self) -> usize
+  {
+    let inner = self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison internal fragmentation");
+    inner.slack.values().sum()
+  }
+
+  // (data pointer, slack) of the live allocations with at least min_waste bytes of slack, in
+  // address order
+  pub fn wasteful_allocations(&self, min_waste: usize) -> Vec<(NonNull<u8>, usize)>
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison wasteful");
+    inner
+      .live_metas()
+      .iter()
+      .map(|meta| {
+        let ptr = meta.data_location();
+        (
+          ptr,
+          inner.slack.get(&ptr.addr().get()).copied().unwrap_or(0),
+        )
+      })
+      .filter(|(_, slack)| *slack >= min_waste)
+      .collect()
+  }
+
+  // padding and headers of every live allocation, the bytes bytes_in_use doesn't see
+  pub fn overhead_bytes(&self) -> usize
+  {
+    self
+      .live_allocations()
+      .iter()
+      .map(|(meta, _)| meta.total_size() - meta.layout.size())
+      .sum()
+  }
+
+  // a node per block, free or live, plus the live map and the location and slack side tables
+  // counted at key and value size per entry. the maps' own tree nodes come from the system
+  // allocator and aren't counted
+  pub fn bookkeeping_bytes(&self) -> usize
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison bookkeeping");
+    let blocks = list_metas(&inner.list).len() + inner.live.len();
+    let cached = usize::from(inner.last_freed.is_some());
+    let live = inner.live.len() * size_of::<(usize, LiveEntry)>();
+    let locations = inner.locations.len() * size_of::<(usize, &'static Location<'static>)>();
+    let slack = inner.slack.len() * size_of::<(usize, usize)>();
+    (blocks + cached) * NODE_SIZE + live + locations + slack
+  }
+
+  // pages this allocator currently holds, released pages no longer count
+  pub fn committed_bytes(&self) -> usize
+  {
+    let stats = self.stats();
+    (stats.pages_committed - stats.pages_released) * PAGE_SIZE
+  }
+
+  // see AllocStats::merges_performed
+  pub fn merges_performed(&self) -> u64
+  {
+    self.stats().merges_performed as u64
+  }
+
+  // (base, bytes) of every run of contiguous pages this allocator holds, in address order. pages
+  // of other allocators interleave with ours on the shared fake heap, so one allocator's memory
+  // is usually split over several segments
+  pub fn segments(&self) -> Vec<(NonNull<u8>, usize)>
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison segments");
+    let pages = pages_of(
+      list_metas(&inner.list)
+        .iter()
+        .chain(inner.live_metas().iter()),
+    );
+
+    let mut ret: Vec<(NonNull<u8>, usize)> = Vec::new();
+    for page in pages
+    {
+      match ret.last_mut()
+      {
+        Some(seg) if seg.0.addr().get() + seg.1 == page.addr() => seg.1 += PAGE_SIZE,
+        _ => ret.push((NonNull::new(page).unwrap(), PAGE_SIZE)),
+      }
+    }
+    ret
+  }
+
+  // successful allocations counted by requested alignment, see ALIGN_BUCKETS
+  pub fn align_histogram(&self) -> [usize; ALIGN_BUCKETS]
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison align histogram")
+      .align_counts
+  }
+
+  // total size of every block on the free list, headers included. the block set_last_freed_cache
+  // parks isn't on it and doesn't count
+  pub fn free_bytes(&self) -> usize
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison free bytes")
+      .free_bytes()
+  }
+
+  // (class, count) of the free blocks per power of two size class, a block of total size n lands
+  // in the largest power of two <= n. only non empty classes, smallest first
+  pub fn fragmentation_by_class(&self) -> Vec<(usize, usize)>
+  {
+    let mut classes: Vec<usize> = self
+      .free_blocks()
+      .iter()
+      .map(|meta| 1 << meta.total_size().ilog2())
+      .collect();
+    classes.sort();
+
+    let mut ret: Vec<(usize, usize)> = Vec::new();
+    for class in classes
+    {
+      match ret.last_mut()
+      {
+        Some(last) if last.0 == class => last.1 += 1,
+        _ => ret.push((class, 1)),
+      }
+    }
+    ret
+  }
+
+  // usable size -> number of free blocks with exactly that usable size
+  pub fn free_size_distribution(&self) -> BTreeMap<usize, usize>
+  {
+    let mut ret = BTreeMap::new();
+    for meta in self.free_blocks()
+    {
+      *ret.entry(meta.usable_size()).or_insert(0) += 1;
+    }
+    ret
+  }
+
+  // once dealloc takes the free list above cap, whole free pages are handed back to the fake heap
+  // until it is under the cap again or nothing is left to trim. partially used pages can't be
+  // released, so fragmentation can still keep the free list above the cap
+  pub fn set_max_free_bytes(&self, cap: Option<usize>)
+  {
+    let mut inner = self.tex.lock().expect("Meta alloc tex poison max free");
+    inner.max_free_bytes = cap;
+    inner.trim_to_cap();
+  }
+
+  // free blocks bigger than threshold (headers included) are left alone while a smaller block can
+  // serve the request, so small allocations don't chip away at large contiguous regions
+  pub fn set_preserve_large(&self, threshold: Option<usize>)
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison preserve large")
+      .preserve_large = threshold;
+  }
+
+  // records how many free list nodes every alloc call scans, a clock free stand in for latency.
+  // turning it on starts a new measurement
+  pub fn set_scan_timing(&self, enabled: bool)
+  {
+    let mut inner = self.tex.lock().expect("Meta alloc tex poison scan timing");
+    inner.scan_timing = enabled;
+    if enabled
+    {
+      inner.scan_sum = 0;
+      inner.scan_count = 0;
+    }
+  }
+
+  // while on, dealloc parks the freed block instead of putting it on the free list and the next
+  // alloc of the layout it was allocated with gets it back without scanning. any other alloc or
+  // dealloc flushes it to the free list first, until then free_blocks, free_bytes, fits_count,
+  // max_allocatable, walk_heap and validate don't see it. turning the cache off flushes it right
+  // away
+  pub fn set_last_freed_cache(&self, enabled: bool)
+  {
+    let mut inner = self.tex.lock().expect("Meta alloc tex poison last freed cache");
+    inner.cache_last_freed = enabled;
+    if !enabled
+    {
+      inner.flush_last_freed();
+    }
+  }
+
+  // 0 until an alloc has been timed
+  pub fn avg_scan_length(&self) -> f64
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison avg scan");
+    if inner.scan_count == 0
+    {
+      0.0
+    }
+    else
+    {
+      inner.scan_sum as f64 / inner.scan_count as f64
+    }
+  }
+
+  pub fn checkpoint(&self) -> Checkpoint
+  {
+    let mut inner = self.tex.lock().expect("Meta alloc tex poison checkpoint");
+    inner.flush_last_freed();
+    Checkpoint {
+      free: list_metas(&inner.list),
+      live: inner
+        .live
+        .values()
+        .map(|(node, label)| (unsafe { (*node.as_ptr()).elem().clone() }, *label))
+        .collect(),
+      locations: inner.locations.clone(),
+      slack: inner.slack.clone(),
+    }
+  }
+
+  /// puts the free list and the live allocations back the way they were at cp. pages committed
+  /// since then go back to the fake heap, the shared bump pointer itself is left alone since other
+  /// allocators may have moved it too
+  ///
+  /// # Safety
+  /// nothing allocated after cp may be used afterwards. allocations live at cp are live again,
+  /// even if they were freed in between. panics if a page of cp has been trimmed away since
+  pub unsafe fn restore(&self, cp: Checkpoint)
+  {
+    let mut inner = self.tex.lock().expect("Meta alloc tex poison restore");
+    assert!(inner.slab.is_none(), "slab allocators can't be restored");
+
+    let now = pages_of(
+      list_metas(&inner.list)
+        .iter()
+        .chain(inner.live_metas().iter()),
+    );
+    let then = pages_of(cp.free.iter().chain(cp.live.iter().map(|(meta, _)| meta)));
+    assert!(
+      then
+        .iter()
+        .all(|page| now.iter().any(|x| x.addr() == page.addr())),
+      "pages of the checkpoint were released since"
+    );
+
+    inner.list.replace(core::iter::empty());
+    inner.live.clear();
+    inner.last_freed = None;
+    inner.locations = cp.locations;
+    inner.slack = cp.slack;
+
+    for page in now
+    {
+      if !then.iter().any(|x| x.addr() == page.addr())
+      {
+        release_page(page);
+        inner.stats.pages_released += 1;
+      }
+    }
+
+    inner.list.replace(cp.free.into_iter().map(meta_write));
+    inner.stats.bytes_in_use = 0;
+    for (meta, label) in cp.live
+    {
+      inner.stats.bytes_in_use += meta.usable_size();
+      let node = meta_write(meta);
+      inner
+        .live
+        .insert(node_to_data_ptr(node).addr(), (node, label));
+    }
+  }
+
+  /// frees everything at once: every page this allocator holds becomes a blank free block again,
+  /// merged with its neighbours, and no page goes back to the fake heap, so allocations after this
+  /// don't have to commit anything. counters other than bytes_in_use are kept
+  ///
+  /// # Safety
+  /// every pointer this allocator handed out is invalid afterwards and must not be used or freed
+  pub unsafe fn reset_to_free(&self)
+  {
+    let mut inner = self.tex.lock().expect("Meta alloc tex poison reset to free");
+    assert!(inner.slab.is_none(), "slab allocators can't be reset");
+    inner.flush_last_freed();
+
+    let pages = pages_of(
+      list_metas(&inner.list)
+        .iter()
+        .chain(inner.live_metas().iter()),
+    );
+    inner.list.replace(core::iter::empty());
+    inner.live.clear();
+    inner.locations.clear();
+    inner.slack.clear();
+    inner.stats.bytes_in_use = 0;
+
+    for page in pages
+    {
+      let meta = MetaData::new_blank(NonNull::new(page).unwrap(), PAGE_SIZE)
+        .expect("page too small for a block header");
+      let node = meta_write(meta);
+      unsafe { inner.dealloc(node_to_data_ptr(node), PAGE_LAYOUT) };
+    }
+    inner.debug_validate();
+  }
+
+  // pages this allocator may hold at once, allocations that need more fail like an exhausted heap
+  pub fn set_max_pages(&self, max: Option<usize>)
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison max pages")
+      .max_pages = max;
+  }
+
+  // called once when an allocation can't be served, instead of returning null right away
+  pub fn set_oom_handler(&self, f: fn(Layout) -> OomAction)
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison oom handler")
+      .oom_handler = Some(f);
+  }
+
+  // gives the whole pages inside the free block starting at base back to the fake heap, like
+  // set_max_free_bytes trimming does. false if base is no free block or the block doesn't cover a
+  // whole page (plus room for the headers of what is left around it)
+  pub fn release_block(&self, base: NonNull<u8>) -> bool
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison release block")
+      .trim_first(|meta| meta.base == base)
+  }
+
+  // FirstFit unless changed
+  pub fn set_fit_strategy(&self, strategy: FitStrategy)
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison fit strategy")
+      .fit_strategy = strategy;
+  }
+
+  // line size alloc_cache_aligned aligns to, 64 unless changed. panics unless bytes is a power of
+  // two
+  pub fn set_cache_line(&self, bytes: usize)
+  {
+    assert!(bytes.is_power_of_two(), "cache line of {} bytes", bytes);
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison cache line")
+      .cache_line = bytes;
+  }
+
+  // the next n allocations go through as usual, every one after them returns null no matter how
+  // much memory is free, like an exhausted heap (the oom handler still runs). None turns it off
+  pub fn set_fail_after(&self, n: Option<usize>)
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison fail after")
+      .fail_after = n;
+  }
+
+  // while frozen every alloc and dealloc panics, to prove a region of code doesn't touch the
+  // allocator. checked before taking the lock so the panic doesn't poison it
+  pub fn freeze(&self)
+  {
+    self.frozen.store(true, Ordering::Relaxed);
+  }
+
+  pub fn unfreeze(&self)
+  {
+    self.frozen.store(false, Ordering::Relaxed);
+  }
+
+  fn check_frozen(&self)
+  {
+    assert!(
+      !self.frozen.load(Ordering::Relaxed),
+      "allocation while frozen"
+    );
+  }
+
+  // panics if the free list is out of order, overlapping, holds unmerged neighbours (unless
+  // coalescing is lazy) or reaches outside of the committed part of the fake heap
+  pub fn validate(&self)
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison validate")
+      .validate();
+  }
+
+  // dealloc already merges neighbours eagerly, so this normally returns 0 unless coalescing is
+  // lazy. returns the total size of every free block that got absorbed into its left neighbour
+  pub fn coalesce_free(&self) -> usize
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison coalesce")
+      .coalesce_free()
+  }
+
+  // the bool is true when the allocation had to commit at least one new page from the fake heap,
+  // so benchmarks can tell allocations served from already committed memory from cold ones
+  pub fn alloc_traced(&self, layout: Layout) -> (Option<NonNull<u8>>, bool)
+  {
+    self.alloc_labeled(layout, 0, 0, false)
+  }
+
+  // ids are unique per allocator and increase with every call, starting at 1
+  pub fn alloc_with_id(&self, layout: Layout) -> Option<(NonNull<u8>, u64)>
+  {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    self
+      .alloc_labeled(layout, id, 0, false)
+      .0
+      .map(|ptr| (ptr, id))
+  }
+
+  // tag names the subsystem the allocation is accounted to, see bytes_by_tag. 0 is untagged
+  pub fn alloc_tagged(&self, layout: Layout, tag: u16) -> Option<NonNull<u8>>
+  {
+    self.alloc_labeled(layout, 0, tag, false).0
+  }
+
+  // alloc that remembers the caller's file, line and column for check_leaks and location_of.
+  // the location lives in a side table, MetaData stays the size it is
+  #[track_caller]
+  pub fn alloc_located(&self, layout: Layout) -> Option<NonNull<u8>>
+  {
+    let location = Location::caller();
+    let ptr = self.alloc_labeled(layout, 0, 0, false).0?;
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison alloc located")
+      .locations
+      .insert(ptr.addr().get(), location);
+    Some(ptr)
+  }
+
+  // where a live allocation made by alloc_located was requested
+  pub fn location_of(&self, ptr: *mut u8) -> Option<&'static Location<'static>>
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison location of")
+      .locations
+      .get(&ptr.addr())
+      .copied()
+  }
+
+  // the label of a live allocation, None once it is freed
+  pub fn label_of(&self, ptr: *mut u8) -> Option<AllocLabel>
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison label of")
+      .live
+      .get(&ptr.addr())
+      .map(|(_, label)| *label)
+  }
+
+  // alloc for a size and align only known at runtime, without unwrapping the Layout first. free
+  // the result with Layout::from_size_align(size, align)
+  pub fn alloc_checked(&self, size: usize, align: usize) -> Result<NonNull<u8>, AllocError>
+  {
+    if !align.is_power_of_two()
+    {
+      return Err(AllocError::BadAlign);
+    }
+    let layout = Layout::from_size_align(size, align).map_err(|_| AllocError::BadLayout)?;
+    self
+      .alloc_labeled(layout, 0, 0, false)
+      .0
+      .ok_or(AllocError::OutOfMemory)
+  }
+
+  // header and label of every allocation that has not been freed yet, oldest first
+  pub fn live_allocations(&self) -> Vec<(MetaData, AllocLabel)>
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison live");
+    let mut ret: Vec<(MetaData, AllocLabel)> = inner
+      .live
+      .values()
+      .map(|(node, label)| (unsafe { (*node.as_ptr()).elem().clone() }, *label))
+      .collect();
+    ret.sort_by_key(|(_, label)| label.seq);
+    ret
+  }
+
+  // true if ptr is the data pointer of an allocation of this allocator that hasn't been freed
+  pub fn owns(&self, ptr: *mut u8) -> bool
+  {
+    self.metadata_for(ptr).is_some()
+  }
+
+  // the header of a live allocation, None for foreign, freed or interior pointers. the layout
+  // size can be larger than requested when a too small leftover got folded into the block
+  pub fn metadata_for(&self, ptr: *mut u8) -> Option<MetaData>
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison metadata for");
+    inner
+      .find_live(ptr)
+      .map(|node| unsafe { (*node.as_ptr()).elem().clone() })
+  }
+
+  // (data pointer, header layout) of the live allocation whose usable region holds addr. only the
+  // last allocation starting at or below addr can hold it, headers and padding in front of the
+  // data belong to none
+  pub fn allocation_containing(&self, addr: *mut u8) -> Option<(NonNull<u8>, Layout)>
+  {
+    let inner = self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison allocation containing");
+    let (_, (node, _)) = inner.live.range(..=addr.addr()).next_back()?;
+    let meta = unsafe { (*node.as_ptr()).elem() };
+    let start = meta.data_location().addr().get();
+    (start..start + meta.usable_size())
+      .contains(&addr.addr())
+      .then(|| (meta.data_location(), meta.layout))
+  }
+
+  // largest size a NODE_ALIGN aligned allocation can have right now without committing a page.
+  // the block set_last_freed_cache parks only serves its own layout and doesn't count
+  pub fn max_allocatable(&self) -> usize
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison max allocatable");
+    if let Some(slab) = &inner.slab
+    {
+      return if slab.free.is_null() { 0 } else { slab.layout.size() };
+    }
+
+    list_metas(&inner.list)
+      .iter()
+      .map(|meta| {
+        let fit = MetaData::new(meta.base, Layout::from_size_align(0, NODE_ALIGN).unwrap());
+        meta.total_size() - fit.extra_size()
+      })
+      .max()
+      .unwrap_or(0)
+  }
+
+  // how many allocations of layout the free blocks can serve without committing a page. every
+  // block is carved front to back the way first fit splits it, headers, padding and leftovers
+  // too small for a header included. the block set_last_freed_cache parks doesn't count
+  pub fn fits_count(&self, layout: Layout) -> usize
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison fits count");
+    if let Some(slab) = &inner.slab
+    {
+      if layout != slab.layout
+      {
+        return 0;
+      }
+      let mut count = 0;
+      let mut cell = slab.free;
+      while !cell.is_null()
+      {
+        count += 1;
+        cell = unsafe { *cell.cast::<*mut u8>() };
+      }
+      return count;
+    }
+
+    let mut count = 0;
+    for meta in list_metas(&inner.list)
+    {
+      let mut block = Some(meta);
+      while let Some(cur) = block.take().filter(|cur| cur.check_compatible(&layout))
+      {
+        count += 1;
+        let used = MetaData::new(cur.base, layout).total_size();
+        let rest = cur.total_size() - used;
+        let rhs = unsafe { cur.base.byte_add(used) };
+        if rest > MetaData::default_meta_offset(rhs) + NODE_SIZE
+        {
+          block = MetaData::new_blank(rhs, rest);
+        }
+      }
+    }
+    count
+  }
+
+  // copies of the metadata of every free block, in address order
+  pub fn free_blocks(&self) -> Vec<MetaData>
+  {
+    list_metas(&self.tex.lock().expect("Meta alloc tex poison free blocks").list)
+  }
+
+  // (base, usable size, bytes to the next free block) for every free block. the last one measures
+  // up to the end of the highest block this allocator holds, the shared bump pointer would count
+  // pages of other allocators too
+  pub fn free_gaps(&self) -> Vec<(NonNull<u8>, usize, usize)>
+  {
+    let top = self
+      .walk_heap()
+      .iter()
+      .map(|block| block.base.addr().get() + block.size)
+      .max()
+      .unwrap_or(0);
+
+    let free = self.free_blocks();
+    let mut ret = Vec::with_capacity(free.len());
+    for (i, meta) in free.iter().enumerate()
+    {
+      let end = meta.base.addr().get() + meta.total_size();
+      let next = free.get(i + 1).map_or(top, |next| next.base.addr().get());
+      ret.push((meta.base, meta.usable_size(), next - end));
+    }
+    ret
+  }
+
+  // every block this allocator owns in address order, free or not. the fake heap is shared by every
+  // MetaAlloc, so instead of scanning it from base to current_top (and walking into pages of other
+  // allocators) the free list and the live map are merged, which covers the same headers. the block
+  // set_last_freed_cache parks is in neither and is left out
+  pub fn walk_heap(&self) -> Vec<BlockInfo>
+  {
+    self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison walk heap")
+      .walk_heap()
+  }
+
+  // blocks and stats are taken under the same lock, so they always agree
+  pub fn report(&self) -> HeapReport
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison report");
+    HeapReport {
+      blocks: inner.walk_heap(),
+      stats: inner.stats,
+      page_size: PAGE_SIZE,
+    }
+  }
+
+  // usable bytes of the live allocations per tag, untagged ones land on 0
+  pub fn bytes_by_tag(&self) -> HashMap<u16, usize>
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison bytes by tag");
+    let mut ret = HashMap::new();
+    for (node, label) in inner.live.values()
+    {
+      *ret.entry(label.tag).or_insert(0) += unsafe { (*node.as_ptr()).elem().usable_size() };
+    }
+    ret
+  }
+
+  // data pointer and seq of the live allocation that has survived the longest
+  pub fn oldest_live_allocation(&self) -> Option<(NonNull<u8>, u64)>
+  {
+    let inner = self.tex.lock().expect("Meta alloc tex poison oldest live");
+    inner
+      .live
+      .values()
+      .min_by_key(|(_, label)| label.seq)
+      .map(|(node, label)| {
+        let meta = unsafe { (*node.as_ptr()).elem() };
+        (meta.data_location(), label.seq)
+      })
+  }
+
+  // panics listing every allocation that is still live
+  pub fn check_leaks(&self)
+  {
+    let live = self.live_allocations();
+    if !live.is_empty()
+    {
+      let leaks: Vec<_> = live
+        .iter()
+        .map(|(meta, label)| {
+          let location = self
+            .location_of(meta.data_location().as_ptr())
+            .map(|location| location.to_string());
+          (label.id, meta.data_location(), meta.layout, location)
+        })
+        .collect();
+      panic!(
+        "{} allocations leaked (id, ptr, layout, location): {:?}",
+        leaks.len(),
+        leaks
+      );
+    }
+  }
+
+  // this allocator's pages as bytes, one raw slice per segment in the order segments returns
+  // them. raw since most of it is padding, slack or free space nobody ever wrote, and the data
+  // regions stay the callers' to write. only bytes that were written and aren't being written
+  // while they are read can be read through them
+  pub fn heap_bytes(&self) -> Vec<*const [u8]>
+  {
+    self
+      .segments()
+      .into_iter()
+      .map(|(base, bytes)| core::ptr::slice_from_raw_parts(base.as_ptr().cast_const(), bytes))
+      .collect()
+  }
+
+  // walk_heap from the top down, the first entries are the blocks closest to the bump pointer
+  pub fn walk_heap_rev(&self) -> Vec<BlockInfo>
+  {
+    let mut ret = self.walk_heap();
+    ret.reverse();
+    ret
+  }
+
+  // hash of the block layout: offset into its segment, size and allocated flag of every block,
+  // plus which segment it is in. addresses themselves differ between allocators, so two of them
+  // driven through the same operations match as long as their pages lined up the same way
+  pub fn fingerprint(&self) -> u64
+  {
+    let segments = self.segments();
+    let mut hasher = std::hash::DefaultHasher::new();
+    for block in self.walk_heap()
+    {
+      let addr = block.base.addr().get();
+      let (index, seg) = segments
+        .iter()
+        .enumerate()
+        .find(|(_, seg)| (seg.0.addr().get()..seg.0.addr().get() + seg.1).contains(&addr))
+        .expect("block outside of every segment");
+      (index, addr - seg.0.addr().get(), block.size, block.allocated).hash(&mut hasher);
+    }
+    hasher.finish()
+  }
+
+  // (start, bytes) of every maximal run of physically adjacent free blocks, headers included. runs
+  // come from walk_heap rather than the free list, so with coalescing working each run is exactly
+  // one free block
+  pub fn contiguous_free_runs(&self) -> Vec<(NonNull<u8>, usize)>
+  {
+    let mut ret: Vec<(NonNull<u8>, usize)> = Vec::new();
+    let mut run_end = None;
+    for block in self.walk_heap()
+    {
+      if block.allocated
+      {
+        run_end = None;
+        continue;
+      }
+      match (ret.last_mut(), run_end)
+      {
+        (Some(run), Some(end)) if end == block.base.addr().get() => run.1 += block.size,
+        _ => ret.push((block.base, block.size)),
+      }
+      run_end = Some(block.base.addr().get() + block.size);
+    }
+    ret
+  }
+
+  // validate, assert_no_overlap, assert_within_committed and assert_strict_ordering in one go,
+  // from a single snapshot taken under one lock. the free list is checked in list order, everything
+  // else on walk_heap
+  pub fn assert_healthy(&self)
+  {
+    let (heap_start, heap_end) = {
+      let heap = FAKE_HEAP.lock().expect("FAKE HEAP POISON HEALTHY");
+      (heap.base.addr(), heap.base.addr() + heap.current_top)
+    };
+    let (free, blocks, lazy) = {
+      let inner = self.tex.lock().expect("Meta alloc tex poison healthy");
+      let free: Vec<_> = inner
+        .list
+        .iterate()
+        .map(|node| (node, unsafe { (*node.as_ptr()).elem().clone() }))
+        .collect();
+      (free, inner.walk_heap(), inner.lazy_coalesce.is_some())
+    };
+
+    for (i, (node, meta)) in free.iter().enumerate()
+    {
+      assert_eq!(
+        meta.meta_location(),
+        *node,
+        "unhealthy heap: free block {:p} header is not where its metadata places it",
+        meta.base
+      );
+      if let Some((_, next)) = free.get(i + 1)
+      {
+        assert!(
+          meta.base < next.base,
+          "unhealthy heap: free list not strictly ordered at {}: {:p} followed by {:p}",
+          i,
+          meta.base,
+          next.base
+        );
+      }
+    }
+
+    for (i, block) in blocks.iter().enumerate()
+    {
+      let start = block.base.addr().get();
+      let end = start + block.size;
+      assert!(
+        start >= heap_start && end <= heap_end,
+        "unhealthy heap: block {:p}..{:#x} is outside the committed heap {:#x}..{:#x}",
+        block.base,
+        end,
+        heap_start,
+        heap_end
+      );
+      if let Some(next) = blocks.get(i + 1)
+      {
+        let next_start = next.base.addr().get();
+        assert!(
+          end <= next_start,
+          "unhealthy heap: blocks {:p}..{:#x} (allocated {}) and {:p} (allocated {}) overlap",
+          block.base,
+          end,
+          block.allocated,
+          next.base,
+          next.allocated
+        );
+        assert!(
+          lazy || block.allocated || next.allocated || end != next_start,
+          "unhealthy heap: adjacent free blocks {:p} and {:p} were not merged",
+          block.base,
+          next.base
+        );
+      }
+    }
+  }
+
+  // panics on the first pair of free list neighbours whose bases are equal or descending. dealloc
+  // inserts before the first block with a greater base, so a duplicate base would go unnoticed
+  // there and leave the list out of order from then on
+  pub fn assert_strict_ordering(&self)
+  {
+    let free = self.free_blocks();
+    for (i, pair) in free.windows(2).enumerate()
+    {
+      assert!(
+        pair[0].base < pair[1].base,
+        "free list not strictly ordered at {}: {:p} followed by {:p}",
+        i,
+        pair[0].base,
+        pair[1].base
+      );
+    }
+  }
+
+  // panics with the offending block if a live allocation reaches below the fake heap or past the
+  // shared bump pointer, which only a broken split or page commit can cause
+  pub fn assert_within_committed(&self)
+  {
+    let (heap_start, heap_end) = {
+      let heap = FAKE_HEAP.lock().expect("FAKE HEAP POISON WITHIN COMMITTED");
+      (heap.base.addr(), heap.base.addr() + heap.current_top)
+    };
+
+    for (meta, label) in self.live_allocations()
+    {
+      let start = meta.base.addr().get();
+      let end = start + meta.total_size();
+      assert!(
+        start >= heap_start && end <= heap_end,
+        "live block {:p}..{:#x} ({:?}, id {}) is outside the committed heap {:#x}..{:#x}",
+        meta.base,
+        end,
+        meta.layout,
+        label.id,
+        heap_start,
+        heap_end
+      );
+    }
+  }
+
+  // panics with the offending pair if two blocks, free or live, share any byte
+  pub fn assert_no_overlap(&self)
+  {
+    for pair in self.walk_heap().windows(2)
+    {
+      let end = pair[0].base.addr().get() + pair[0].size;
+      assert!(
+        end <= pair[1].base.addr().get(),
+        "blocks {:p}..{:#x} (allocated {}) and {:p} (allocated {}) overlap",
+        pair[0].base,
+        end,
+        pair[0].allocated,
+        pair[1].base,
+        pair[1].allocated
+      );
+    }
+  }
+
+  fn alloc_labeled(
+    &self,
+    layout: Layout,
+    id: u64,
+    tag: u16,
+    zeroed: bool,
+  ) -> (Option<NonNull<u8>>, bool)
+  {
+    self.check_frozen();
+    let (ptr, committed) = self.alloc_once(layout, id, tag, zeroed);
+    if ptr.is_some()
+    {
+      return (ptr, committed);
+    }
+
+    // the handler runs without the lock held so it can free through this allocator
+    let handler = self.tex.lock().expect("Meta alloc tex poison oom").oom_handler;
+    match handler.map(|f| f(layout))
+    {
+      Some(OomAction::Retry) =>
+      {
+        let (ptr, retry_committed) = self.alloc_once(layout, id, tag, zeroed);
+        (ptr, committed || retry_committed)
+      }
+      _ => (None, committed),
+    }
+  }
+
+  fn alloc_once(
+    &self,
+    layout: Layout,
+    id: u64,
+    tag: u16,
+    zeroed: bool,
+  ) -> (Option<NonNull<u8>>, bool)
+  {
+    let mut inner = self.tex.lock().expect("Meta alloc tex poison alloc");
+    inner.stats.lock_acquisitions += 1;
+    let fill = if zeroed { Some(0) } else { inner.alloc_fill };
+    unsafe { inner.alloc_tracked(layout, id, tag, fill) }
+  }
+
+  /// deallocs every pointer under a single lock, neighbours still coalesce as usual
+  ///
+  /// # Safety
+  /// same as GlobalAlloc::dealloc for every pointer in ptrs
+  pub unsafe fn dealloc_batch(&self, ptrs: &[*mut u8])
+  {
+    self.check_frozen();
+    let mut inner = self.tex.lock().expect("Meta alloc tex poison dealloc batch");
+    inner.stats.lock_acquisitions += 1;
+    for ptr in ptrs
+    {
+      // the header knows the real layout, dealloc never looks at this one
+      unsafe { inner.dealloc_tracked(*ptr, Layout::new::<u8>()) };
+    }
+  }
+
+  /// size bytes aligned to the cache line, see set_cache_line. null if the allocation fails or size
+  /// is too large for a Layout
+  ///
+  /// # Safety
+  /// same as GlobalAlloc::alloc with a layout of size and the cache line alignment, which is also
+  /// the layout to free it with
+  pub unsafe fn alloc_cache_aligned(&self, size: usize) -> *mut u8
+  {
+    let line = self
+      .tex
+      .lock()
+      .expect("Meta alloc tex poison alloc cache aligned")
+      .cache_line;
+    match Layout::from_size_align(size, line)
+    {
+      Ok(layout) => unsafe { self.alloc(layout) },
+      Err(_) => core::ptr::null_mut(),
+    }
+  }
+
+  /// realloc that can also change the alignment. when the block already has room for new_layout
+  /// at ptr only its header changes, otherwise the first min(old, new) bytes move to a new block.
+  /// null, with ptr left alone, if no new block can be allocated. either way the stats count it
+  /// as an allocation of new_layout and a deallocation of the old block
+  ///
+  /// # Safety
+  /// same as GlobalAlloc::realloc, but new_layout replaces new_size
+  pub unsafe fn realloc_aligned(
+    &self,
+    ptr: *mut u8,
+    old_layout: Layout,
+    new_layout: Layout,
+  ) -> *mut u8
+  {
+    self.check_frozen();
+    {
+      let mut inner = self.tex.lock().expect("Meta alloc tex poison realloc aligned");
+      if let Some(node) = inner.find_live(ptr).filter(|_| inner.slab.is_none())
+      {
+        let meta = unsafe { (*node.as_ptr()).elem_mut() };
+        let fit = MetaData::new(meta.base, new_layout);
+        // same data location means the same header location too, only the layout is rewritten
+        if fit.data_location().as_ptr() == ptr && fit.total_size() <= meta.total_size()
+        {
+          let old_usable = meta.usable_size();
+          meta.layout =
+            Layout::from_size_align(meta.total_size() - fit.extra_size(), new_layout.align())
+              .unwrap();
+          let slack = meta.layout.size() - new_layout.size();
+          let new_usable = meta.usable_size();
+          inner.set_slack(ptr, slack);
+
+          // what the alloc and dealloc of the move below would have counted
+          inner.stats.allocations += 1;
+          inner.stats.deallocations += 1;
+          inner.stats.bytes_in_use = inner.stats.bytes_in_use - old_usable + new_usable;
+          inner.count_align(new_layout);
+          return ptr;
+        }
+      }
+    }
+
+    let new = unsafe { self.alloc(new_layout) };
+    if !new.is_null()
+    {
+      unsafe {
+        core::ptr::copy_nonoverlapping(ptr, new, old_layout.size().min(new_layout.size()));
+        self.dealloc(ptr, old_layout);
+      }
+    }
+    new
+  }
+
+  /// turns two live allocations where b's block starts right where a's ends into one allocation at
+  /// a, b's header becomes usable bytes. None, and nothing changes, unless both are live here, are
+  /// adjacent in that order and share an alignment. b counts as deallocated
+  ///
+  /// # Safety
+  /// both must be allocations of this allocator that the caller owns. b must not be freed
+  /// afterwards, its bytes now belong to the returned pointer which covers everything from a to
+  /// the end of b's block and is freed once, with a layout of a's alignment. b's data stays where
+  /// it was, inside the combined region
+  pub unsafe fn coalesce_owned(&self, a: *mut u8, b: *mut u8) -> Option<*mut u8>
+  {
+    self.check_frozen();
+    let mut inner = self.tex.lock().expect("Meta alloc tex poison coalesce owned");
+    if inner.slab.is_some()
+    {
+      return None;
+    }
+
+    let node_a = inner.find_live(a)?;
+    let node_b = inner.find_live(b)?;
+    let meta_b = unsafe { (*node_b.as_ptr()).elem().clone() };
+    let meta_a = unsafe { (*node_a.as_ptr()).elem_mut() };
+    if meta_a.base.addr().get() + meta_a.total_size() != meta_b.base.addr().get()
+      || meta_a.layout.align() != meta_b.layout.align()
+    {
+      return None;
+    }
+
+    meta_a.layout = Layout::from_size_align(
+      meta_a.layout.size() + meta_b.total_size(),
+      meta_a.layout.align(),
+    )
+    .unwrap();
+    inner.untrack(b);
+    inner.locations.remove(&b.addr());
+    if let Some(slack_b) = inner.slack.remove(&b.addr())
+    {
+      *inner.slack.entry(a.addr()).or_insert(0) += slack_b;
+    }
+    inner.stats.deallocations += 1;
+    inner.stats.bytes_in_use += meta_b.total_size() - meta_b.usable_size();
+    Some(a)
+  }
+}
+
+impl MetaAllocInner
+{
+  const fn new() -> Self
+  {
+    Self {
+      list: List::new(),
+      live: BTreeMap::new(),
+      stats: AllocStats::new(),
+      align_counts: [0; ALIGN_BUCKETS],
+      max_free_bytes: None,
+      preserve_large: None,
+      slab: None,
+      max_pages: None,
+      oom_handler: None,
+      alloc_fill: None,
+      fit_strategy: FitStrategy::FirstFit,
+      cache_line: 64,
+      region_offset: 0,
+      locations: BTreeMap::new(),
+      slack: BTreeMap::new(),
+      fail_after: None,
+      lazy_coalesce: None,
+      scan_timing: false,
+      scan_sum: 0,
+      scan_count: 0,
+      cache_last_freed: false,
+      last_freed: None,
+    }
+  }
+
+  // alloc plus the bookkeeping every public entry point wants: stats, live map and ids. fill is
+  // written over the whole usable region
+  unsafe fn alloc_tracked(
+    &mut self,
+    layout: Layout,
+    id: u64,
+    tag: u16,
+    fill: Option<u8>,
+  ) -> (Option<NonNull<u8>>, bool)
+  {
+    match &mut self.fail_after
+    {
+      Some(0) => return (None, false),
+      Some(left) => *left -= 1,
+      None => (),
+    }
+
+    let pages_before = self.stats.pages_committed;
+    let steps_before = self.stats.free_list_steps;
+    let ptr = NonNull::new(unsafe { self.alloc(layout) });
+    if self.scan_timing
+    {
+      self.scan_sum += self.stats.free_list_steps - steps_before;
+      self.scan_count += 1;
+    }
+    if let Some(ptr) = ptr
+    {
+      let usable = match &self.slab
+      {
+        Some(slab) => slab.cell_size,
+        None =>
+        {
+          let node = raw_to_existing_node(ptr.as_ptr());
+          let meta = unsafe { (*node.as_ptr()).elem().clone() };
+          let slack = meta.layout.size() - layout.size();
+          if slack > 0
+          {
+            self.slack.insert(ptr.addr().get(), slack);
+          }
+          let seq = self.stats.allocations as u64;
+          self
+            .live
+            .insert(ptr.addr().get(), (node, AllocLabel { id, seq, tag }));
+          meta.usable_size()
+        }
+      };
+      self.stats.allocations += 1;
+      self.stats.bytes_in_use += usable;
+      if let Some(byte) = fill
+      {
+        unsafe { ptr.write_bytes(byte, usable) };
+      }
+
+      self.count_align(layout);
+    }
+    (ptr, self.stats.pages_committed != pages_before)
+  }
+
+  fn set_slack(&mut self, ptr: *mut u8, slack: usize)
+  {
+    if slack > 0
+    {
+      self.slack.insert(ptr.addr(), slack);
+    }
+    else
+    {
+      self.slack.remove(&ptr.addr());
+    }
+  }
+
+  fn count_align(&mut self, layout: Layout)
+  {
+    let bucket = (layout.align().trailing_zeros() as usize).min(ALIGN_BUCKETS - 1);
+    self.align_counts[bucket] += 1;
+  }
+
+  // counterpart of alloc_tracked
+  unsafe fn dealloc_tracked(&mut self, ptr: *mut u8, layout: Layout)
+  {
+    self.locations.remove(&ptr.addr());
+    let slack = self.slack.remove(&ptr.addr()).unwrap_or(0);
+    if let Some(slab) = &mut self.slab
+    {
+      let cell_size = slab.cell_size;
+      unsafe { slab.dealloc(ptr) };
+      self.stats.deallocations += 1;
+      self.stats.bytes_in_use -= cell_size;
+      return;
+    }
+
+    // a pointer into the middle of a block would have us read user bytes as a header, so nothing
+    // behind ptr is touched before the live map vouches for it
+    let Some(node) = self.untrack(ptr)
+    else
+    {
+      panic!(
+        "dealloc of interior or misaligned pointer {:p}, or one that is not live here",
+        ptr
+      );
+    };
+    self.stats.deallocations += 1;
+    self.stats.bytes_in_use -= unsafe { (*node.as_ptr()).elem().usable_size() };
+    if self.cache_last_freed
+    {
+      self.flush_last_freed();
+      let held = unsafe { (*node.as_ptr()).elem().layout };
+      let requested = Layout::from_size_align(held.size() - slack, held.align()).unwrap();
+      self.last_freed = Some((node, requested));
+    }
+    else
+    {
+      unsafe { self.dealloc(ptr, layout) };
+    }
+    self.trim_to_cap();
+    self.debug_validate();
+  }
+
+  unsafe fn try_add_page(&mut self) -> bool
+  {
+    let pg = commit_page(&mut self.stats, self.max_pages);
+    if pg.is_null()
+    {
+      false
+    }
+    else
+    {
+      let offset = core::mem::take(&mut self.region_offset);
+      let base = unsafe { NonNull::new(pg).unwrap().byte_add(offset) };
+      let meta = MetaData::new_blank(base, PAGE_SIZE - offset)
+        .expect("page too small for a block header");
+      let node = meta_write(meta);
+      unsafe {
+        self.dealloc(node_to_data_ptr(node), PAGE_LAYOUT);
+      };
+      true
+    }
+  }
+
+  unsafe fn alloc(&mut self, layout: Layout) -> *mut u8
+  {
+    if let Some(slab) = &mut self.slab
+    {
+      return slab.alloc(layout, &mut self.stats, self.max_pages);
+    }
+
+    if let Some((node, cached)) = self.last_freed.take()
+    {
+      if cached == layout
+      {
+        return node_to_data_ptr(node);
+      }
+      unsafe { self.dealloc(node_to_data_ptr(node), cached) };
+    }
+
+    // a request larger than a page takes one commit per page until enough of them line up. one
+    // that can't fit in the free list plus every page left would commit, and keep, the rest of
+    // the shared heap before failing
+    let needed = layout.size() + NODE_SIZE;
+    if needed > PAGE_SIZE && needed > heap_left() + self.free_bytes()
+    {
+      return core::ptr::null_mut();
+    }
+
+    // this loops instead of recursing once per page
+    loop
+    {
+      // blocks above preserve_large are only carved once nothing smaller fits
+      if let Some(limit) = self.preserve_large
+        && let Some(ptr) = unsafe { self.find_fit(layout, limit) }
+      {
+        return ptr;
+      }
+      if let Some(ptr) = unsafe { self.find_fit(layout, usize::MAX) }
+      {
+        return ptr;
+      }
+
+      // unmerged neighbours may add up to a fit, and a new page would never merge onto them
+      if self.lazy_coalesce.is_some() && self.coalesce_free() > 0
+      {
+        continue;
+      }
+      if !unsafe { self.try_add_page() }
+      {
+        return core::ptr::null_mut();
+      }
+    }
+  }
+
+  // carves the compatible free block of at most max_block bytes the fit strategy picks
+  unsafe fn find_fit(&mut self, layout: Layout, max_block: usize) -> Option<*mut u8>
+  {
+    let target = match self.fit_strategy
+    {
+      FitStrategy::FirstFit => None,
+      FitStrategy::MinWaste => Some(self.min_waste_index(layout, max_block)?),
+    };
+
+    let mut index = 0;
+    let node = self.list.remove(|current| {
+      let hit = match target
+      {
+        Some(target) => index == target,
+        None =>
+        {
+          self.stats.free_list_steps += 1;
+          current.total_size() <= max_block && current.check_compatible(&layout)
+        }
+      };
+      index += 1;
+      hit
+    })?;
+
+    let (ret_node, remaining) = node_split(node, layout);
+    if let Some(rem) = remaining
+    {
+      unsafe { self.dealloc(node_to_data_ptr(rem), (*rem.as_ptr()).elem().layout) };
+    }
+    self.debug_validate();
+    Some(node_to_data_ptr(ret_node))
+  }
+
+  // position in the free list of the compatible block fit_waste likes best
+  fn min_waste_index(&mut self, layout: Layout, max_block: usize) -> Option<usize>
+  {
+    let mut best: Option<(usize, usize)> = None;
+    for (index, node) in self.list.iterate().enumerate()
+    {
+      self.stats.free_list_steps += 1;
+      let meta = unsafe { (*node.as_ptr()).elem() };
+      if meta.total_size() <= max_block && meta.check_compatible(&layout)
+      {
+        let waste = fit_waste(meta, layout);
+        if best.is_none_or(|(_, best_waste)| waste < best_waste)
+        {
+          best = Some((index, waste));
+        }
+      }
+    }
+    best.map(|(index, _)| index)
+  }
+
+  unsafe fn dealloc(&mut self, ptr: *mut u8, _layout: Layout)
+  {
+    let node = free_node(raw_to_existing_node(ptr));
+    match self.lazy_coalesce
+    {
+      Some(threshold) =>
+      {
+        self.list.insert_sorted(node, false);
+        if self.list.iterate().count() > threshold
+        {
+          self.coalesce_free();
+        }
+      }
+      None => self.stats.merges_performed += self.list.insert_sorted(node, true),
+    }
+  }
+
+  fn walk_heap(&self) -> Vec<BlockInfo>
+  {
+    let mut ret: Vec<BlockInfo> = list_metas(&self.list)
+      .iter()
+      .map(|meta| BlockInfo::new(meta, false))
+      .chain(
+        self
+          .live_metas()
+          .iter()
+          .map(|meta| BlockInfo::new(meta, true)),
+      )
+      .collect();
+    ret.sort_by_key(|block| block.base);
+    ret
+  }
+
+  fn flush_last_freed(&mut self)
+  {
+    if let Some((node, layout)) = self.last_freed.take()
+    {
+      unsafe { self.dealloc(node_to_data_ptr(node), layout) };
+    }
+  }
+
+  // the node of the live allocation whose data pointer is ptr
+  fn find_live(&self, ptr: *mut u8) -> Option<NonNull<Node<MetaData>>>
+  {
+    self.live.get(&ptr.addr()).map(|(node, _)| *node)
+  }
+
+  // find_live that also drops the allocation from the live map, so dealloc can hand its node to
+  // the free list
+  fn untrack(&mut self, ptr: *mut u8) -> Option<NonNull<Node<MetaData>>>
+  {
+    self.live.remove(&ptr.addr()).map(|(node, _)| node)
+  }
+
+  // copies of the headers of every live allocation, in address order
+  fn live_metas(&self) -> Vec<MetaData>
+  {
+    self
+      .live
+      .values()
+      .map(|(node, _)| unsafe { (*node.as_ptr()).elem().clone() })
+      .collect()
+  }
+
+  fn free_bytes(&self) -> usize
+  {
+    self
+      .list
+      .iterate()
+      .map(|node| unsafe { (*node.as_ptr()).elem().total_size() })
+      .sum()
+  }
+
+  fn trim_to_cap(&mut self)
+  {
+    if let Some(cap) = self.max_free_bytes
+    {
+      while self.free_bytes() > cap && self.trim_one()
+      {}
+    }
+  }
+
+  // releases the whole pages inside the first free block that has any, the bytes left on either
+  // side go back on the free list as their own blocks. false if no block could be trimmed
+  fn trim_one(&mut self) -> bool
+  {
+    self.trim_first(|_| true)
+  }
+
+  // trim_one limited to the free blocks pick accepts
+  fn trim_first(&mut self, pick: impl Fn(&MetaData) -> bool) -> bool
+  {
+    let Some(node) = self
+      .list
+      .remove(|meta| pick(meta) && trim_range(meta).is_some())
+    else
+    {
+      return false;
+    };
+    let meta = unsafe { (*node.as_ptr()).elem().clone() };
+    let (pages_start, pages_end) = trim_range(&meta).unwrap();
+    let base = meta.base;
+    let start = base.addr().get();
+    let end = start + meta.total_size();
+
+    for page in (pages_start..pages_end).step_by(PAGE_SIZE)
+    {
+      release_page(unsafe { base.byte_add(page - start).as_ptr() });
+      self.stats.pages_released += 1;
+    }
+
+    let leftovers = [
+      (base, pages_start - start),
+      (unsafe { base.byte_add(pages_end - start) }, end - pages_end),
+    ];
+    for (piece, size) in leftovers
+    {
+      if size > 0
+      {
+        let node = meta_write(
+          MetaData::new_blank(piece, size).expect("trim leftover too small for a block header"),
+        );
+        unsafe { self.dealloc(node_to_data_ptr(node), (*node.as_ptr()).elem().layout) };
+      }
+    }
+    true
+  }
+
+  fn coalesce_free(&mut self) -> usize
+  {
+    let (merges, recovered) = self.list.merge_neighbors();
+    self.stats.merges_performed += merges;
+    recovered
+  }
+
+  // validate after every public dealloc and every split, debug builds only
+  fn debug_validate(&self)
+  {
+    if cfg!(debug_assertions)
+    {
+      self.validate();
+    }
+  }
+
+  fn validate(&self)
+  {
+    let (heap_start, heap_end) = {
+      let heap = FAKE_HEAP.lock().expect("FAKE HEAP POISON VALIDATE");
+      (heap.base.addr(), heap.base.addr() + heap.current_top)
+    };
+
+    let mut prev: Option<&MetaData> = None;
+    for p_node in self.list.iterate()
+    {
+      let meta = unsafe { (*p_node.as_ptr()).elem() };
+      let start = meta.base.addr().get();
+      let end = start + meta.total_size();
+
+      assert_eq!(
+        meta.meta_location(),
+        p_node,
+        "free block {:p} header is not where its metadata places it",
+        meta.base
+      );
+      assert!(
+        start >= heap_start && end <= heap_end,
+        "free block {:p}..{:#x} is outside the committed heap {:#x}..{:#x}",
+        meta.base,
+        end,
+        heap_start,
+        heap_end
+      );
+
+      if let Some(prev) = prev
+      {
+        let prev_end = prev.base.addr().get() + prev.total_size();
+        assert!(
+          prev.base < meta.base,
+          "free list out of order: {:p} before {:p}",
+          prev.base,
+          meta.base
+        );
+        assert!(
+          prev_end <= start,
+          "free blocks {:p} and {:p} overlap",
+          prev.base,
+          meta.base
+        );
+        assert!(
+          self.lazy_coalesce.is_some() || prev_end != start,
+          "adjacent free blocks {:p} and {:p} were not merged",
+          prev.base,
+          meta.base
+        );
+      }
+
+      prev = Some(meta);
+    }
+  }
+}
+
+unsafe impl GlobalAlloc for MetaAlloc
+{
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8
+  {
+    self
+      .alloc_traced(layout)
+      .0
+      .map_or(core::ptr::null_mut(), |ptr| ptr.as_ptr())
+  }
+
+  // zeroes instead of using the alloc fill pattern
+  unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8
+  {
+    self
+      .alloc_labeled(layout, 0, 0, true)
+      .0
+      .map_or(core::ptr::null_mut(), |ptr| ptr.as_ptr())
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+  {
+    self.check_frozen();
+    let mut inner = self.tex.lock().expect("Meta alloc tex poison dealloc");
+    inner.stats.lock_acquisitions += 1;
+    unsafe { inner.dealloc_tracked(ptr, layout) };
+  }
+}
+
+// MetaAlloc without the Mutex for single threaded benchmarks, same block logic but no ids,
+// freezing or oom handler. RefCell keeps it !Sync
+pub struct MetaAllocLocal
+{
+  inner: RefCell<MetaAllocInner>,
+}
+
+impl Default for MetaAllocLocal
+{
+  fn default() -> Self
+  {
+    Self::new()
+  }
+}
+
+impl MetaAllocLocal
+{
+  pub const fn new() -> Self
+  {
+    Self {
+      inner: RefCell::new(MetaAllocInner::new()),
+    }
+  }
+
+  pub fn stats(&self) -> AllocStats
+  {
+    self.inner.borrow().stats
+  }
+
+  pub fn validate(&self)
+  {
+    self.inner.borrow().validate();
+  }
+}
+
+unsafe impl GlobalAlloc for MetaAllocLocal
+{
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8
+  {
+    unsafe { self.inner.borrow_mut().alloc_tracked(layout, 0, 0, None) }
+      .0
+      .map_or(core::ptr::null_mut(), |ptr| ptr.as_ptr())
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+  {
+    unsafe { self.inner.borrow_mut().dealloc_tracked(ptr, layout) };
+  }
+}
+
+impl MetaData
+{
+  pub fn data_location(&self) -> NonNull<u8>
+  {
+    // base + padding + metadata size
+    unsafe { self.base.byte_add(self.extra_size()) }
+  }
+
+  pub fn meta_location(&self) -> NonNull<Node<MetaData>>
+  {
+    // data_location - node size
+    unsafe { self.data_location().byte_sub(NODE_SIZE).cast() }
+  }
+
+  // for align <= NODE_ALIGN this is NODE_SIZE plus at most NODE_ALIGN - 1 bytes to align base,
+  // only over aligned layouts pay for rounding the node up to their alignment
+  pub fn extra_size(&self) -> usize
+  {
+    // bytes to align to `self.layout.align().max(NODE_ALIGN)` so the rest of the calculation is correct
+    let align = self.base.align_offset(self.layout.align().max(NODE_ALIGN));
+
+    NODE_SIZE.next_multiple_of(self.layout.align()) + align
+  }
+
+  pub fn default_meta_offset(base: NonNull<u8>) -> usize
+  {
+    let temp_meta = MetaData::new(base, Layout::from_size_align(0, NODE_ALIGN).unwrap());
+    unsafe { temp_meta.meta_location().byte_offset_from_unsigned(base) }
+  }
+
+  // bytes from data_location to the end of the block. padding an over aligned layout put in front
+  // of the node is not reachable from the data pointer, so it doesn't count
+  pub fn usable_size(&self) -> usize
+  {
+    self.total_size() - self.extra_size()
+  }
+
+  // writes byte over the whole usable region and reads it back
+  #[cfg(test)]
+  pub fn fill_and_check(&self, byte: u8)
+  {
+    let len = self.usable_size();
+    unsafe {
+      let data = self.data_location().as_ptr();
+      data.write_bytes(byte, len);
+
+      let bytes = core::slice::from_raw_parts(data, len);
+      if let Some(offset) = bytes.iter().position(|b| *b != byte)
+      {
+        panic!(
+          "block {:p} reads back {:#x} at offset {} after filling {:#x}",
+          self.base, bytes[offset], offset, byte
+        );
+      }
+    }
+  }
+
+  pub fn total_size(&self) -> usize
+  {
+    self.layout.size() + self.extra_size()
+  }
+
+  pub fn check_compatible(&self, lay: &Layout) -> bool
+  {
+    // usable_size only holds for the block's own alignment, a smaller alignment can still round
+    // the node offset up further (40 bytes of node at 32 alignment takes 64), so always compare
+    // against the header the new layout would need
+    let new_meta = Self::new(self.base, *lay);
+
+    self
+      .total_size()
+      .checked_sub(new_meta.extra_size())
+      .is_some_and(|x| x >= lay.size())
+  }
+
+  pub fn new(base: NonNull<u8>, layout: Layout) -> Self
+  {
+    Self { base, layout }
+  }
+
+  // used to create a blank unallocated node with the correct sizing from a new page
+  // None if size can't even hold the padding and header
+  pub fn new_blank(base: NonNull<u8>, size: usize) -> Option<Self>
+  {
+    let padding = Self::default_meta_offset(base);
+    let total_removed = padding + NODE_SIZE;
+    let ret = Self {
+      base,
+      layout: Layout::from_size_align(size.checked_sub(total_removed)?, NODE_ALIGN).ok()?,
+    };
+
+    Some(ret)
+  }
+}
+
+impl PartialOrd for MetaData
+{
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering>
+  {
+    self.base.partial_cmp(&other.base)
+  }
+}
+
+#[cfg(test)]
+mod meta_tests
+{
+  const ALLOC_COUNT: usize = 1000;
+
+  use core::alloc::Layout;
+  use core::ptr::NonNull;
+  use std::alloc::GlobalAlloc;
+  use std::collections::{BTreeMap, HashSet};
+  use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+  use raw_list::{List, Node};
+
+  use crate::{
+    AllocError, AllocLabel, FitStrategy, MetaAlloc, MetaAllocLocal, MetaData, OomAction,
+    alloc::{
+      FAKE_HEAP, FAKE_HEAP_SIZE, FreeList, LiveEntry, NODE_ALIGN, NODE_SIZE, PAGE_LAYOUT,
+      PAGE_SIZE, meta_write, page_index_of, raw_to_existing_node,
+    },
+    workload::{Rng, run_random_workload},
+  };
+  const LAY: Layout = unsafe { Layout::from_size_align_unchecked(32, 16) };
+
+  #[test]
+  pub fn fifo_alloc()
+  {
+    unsafe {
+      let myalloc: MetaAlloc = MetaAlloc::new();
+      let mut stored: Vec<*mut u8> = Vec::with_capacity(ALLOC_COUNT);
+      for i in 1..ALLOC_COUNT
+      {
+        let a = myalloc.alloc(Layout::from_size_align(i, 8).unwrap());
+        assert!(!a.is_null());
+        assert!(a.addr().is_multiple_of(8));
+        (*raw_to_existing_node(a).as_ptr()).elem().fill_and_check(0xff);
+
+        stored.push(a);
+      }
+      stored.into_iter().enumerate().for_each(|(i, x)| {
+        myalloc.dealloc(x, Layout::from_size_align(i, 8).unwrap());
+      });
+    }
+  }
+
+  #[test]
+  pub fn lifo_alloc()
+  {
+    unsafe {
+      let myalloc: MetaAlloc = MetaAlloc::new();
+      let mut stored = Vec::with_capacity(ALLOC_COUNT);
+      for _ in 0..ALLOC_COUNT
+      {
+        let ptr = myalloc.alloc(LAY);
+        assert!(!ptr.is_null());
+        assert!(ptr.addr().is_multiple_of(LAY.align()));
+        (*raw_to_existing_node(ptr).as_ptr()).elem().fill_and_check(0xff);
+        stored.push(ptr);
+      }
+      stored.into_iter().rev().for_each(|x| {
+        myalloc.dealloc(x, LAY);
+      });
+    }
+  }
+
+  #[test]
+  pub fn page_alloc()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let ptr = myalloc.alloc(PAGE_LAYOUT);
+      assert!(!ptr.is_null());
+      myalloc.dealloc(ptr, PAGE_LAYOUT);
+    }
+  }
+
+  #[test]
+  pub fn alloc_traced_page_commit()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let (first, committed) = myalloc.alloc_traced(LAY);
+      assert!(committed);
+
+      let mut stored = vec![first.unwrap()];
+      loop
+      {
+        let (ptr, committed) = myalloc.alloc_traced(LAY);
+        stored.push(ptr.unwrap());
+        if committed
+        {
+          break;
+        }
+        assert!(stored.len() <= PAGE_SIZE / LAY.size());
+      }
+      assert!(stored.len() > 2);
+      assert_eq!(myalloc.stats().pages_committed, 2);
+
+      stored.into_iter().for_each(|x| {
+        myalloc.dealloc(x.as_ptr(), LAY);
+      });
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  pub fn merge_across_pages()
+  {
+    unsafe {
+      // more than a page can hold, so this can only be served by blocks merged across a page
+      // boundary. other allocators commit pages concurrently, so it may take a few tries before
+      // two of ours end up next to each other
+      let lay = Layout::from_size_align(PAGE_SIZE + PAGE_SIZE / 2, 8).unwrap();
+      let myalloc = MetaAlloc::new();
+      let ptr = myalloc.alloc(lay);
+      assert!(!ptr.is_null());
+      ptr.write_bytes(0xff, lay.size());
+      myalloc.dealloc(ptr, lay);
+      myalloc.validate();
+
+      // the freed block went back as a single node, so the same request fits without a commit
+      let (again, committed) = myalloc.alloc_traced(lay);
+      assert!(!committed);
+      myalloc.dealloc(again.unwrap().as_ptr(), lay);
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  pub fn align_histogram_buckets()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let spread = [(1, 4), (8, 3), (16, 2), (64, 1), (PAGE_SIZE, 1)];
+
+      let mut stored = Vec::new();
+      for (align, count) in spread
+      {
+        for _ in 0..count
+        {
+          let lay = Layout::from_size_align(24, align).unwrap();
+          let ptr = myalloc.alloc(lay);
+          assert!(!ptr.is_null());
+          stored.push((ptr, lay));
+        }
+      }
+
+      let histogram = myalloc.align_histogram();
+      for (align, count) in spread
+      {
+        assert_eq!(histogram[align.trailing_zeros() as usize], count);
+      }
+      assert_eq!(histogram.iter().sum::<usize>(), stored.len());
+
+      stored.into_iter().for_each(|(x, lay)| {
+        myalloc.dealloc(x, lay);
+      });
+    }
+  }
+
+  // fills a single page with LAY blocks, the block that spilled into a new page is freed first
+  unsafe fn exhaust_page(myalloc: &MetaAlloc) -> Vec<*mut u8>
+  {
+    let mut stored = Vec::new();
+    let (first, committed) = myalloc.alloc_traced(LAY);
+    assert!(committed);
+    stored.push(first.unwrap().as_ptr());
+    loop
+    {
+      let (ptr, committed) = myalloc.alloc_traced(LAY);
+      let ptr = ptr.unwrap().as_ptr();
+      if committed
+      {
+        unsafe { myalloc.dealloc(ptr, LAY) };
+        break;
+      }
+      stored.push(ptr);
+    }
+    myalloc.validate();
+    stored
+  }
+
+  unsafe fn free_in_order(myalloc: &MetaAlloc, stored: Vec<*mut u8>)
+  {
+    for x in stored
+    {
+      unsafe { myalloc.dealloc(x, LAY) };
+      myalloc.validate();
+    }
+    assert_eq!(myalloc.stats().bytes_in_use, 0);
+  }
+
+  #[test]
+  pub fn exhaust_page_free_back_to_front()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let mut stored = exhaust_page(&myalloc);
+      stored.reverse();
+      free_in_order(&myalloc, stored);
+    }
+  }
+
+  #[test]
+  pub fn exhaust_page_free_front_to_back()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let stored = exhaust_page(&myalloc);
+      free_in_order(&myalloc, stored);
+    }
+  }
+
+  #[test]
+  pub fn exhaust_page_free_random()
+  {
+    unsafe {
+      for seed in [0, 7, 0xC0FFEE]
+      {
+        let myalloc = MetaAlloc::new();
+        let mut stored = exhaust_page(&myalloc);
+        let mut rng = Rng::new(seed);
+        for i in (1..stored.len()).rev()
+        {
+          stored.swap(i, rng.below(i + 1));
+        }
+        free_in_order(&myalloc, stored);
+      }
+    }
+  }
+
+  #[test]
+  pub fn coalesce_free_recovers_split_blocks()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      assert_eq!(myalloc.coalesce_free(), 0);
+
+      let a = myalloc.alloc(LAY);
+      let b = myalloc.alloc(LAY);
+      assert_eq!(myalloc.coalesce_free(), 0);
+
+      // put a and b back without going through dealloc, leaving three adjacent unmerged blocks
+      let expected = {
+        let mut inner = myalloc.tex.lock().unwrap();
+        let rest = (*inner.list.peek_front().unwrap().as_ptr()).elem().total_size();
+        let node_a = inner.untrack(a).unwrap();
+        let node_b = inner.untrack(b).unwrap();
+        inner.list.push_front(node_b);
+        inner.list.push_front(node_a);
+        (*node_b.as_ptr()).elem().total_size() + rest
+      };
+
+      assert_eq!(myalloc.coalesce_free(), expected);
+      assert_eq!(myalloc.coalesce_free(), 0);
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  pub fn live_allocation_ids()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let tagged: Vec<_> = (0..5)
+        .map(|_| myalloc.alloc_with_id(LAY).unwrap())
+        .collect();
+      assert!(tagged.windows(2).all(|w| w[0].1 < w[1].1));
+
+      let untagged = myalloc.alloc(LAY);
+      myalloc.dealloc(tagged[1].0.as_ptr(), LAY);
+      myalloc.dealloc(tagged[3].0.as_ptr(), LAY);
+
+      let live = myalloc.live_allocations();
+      let ids: Vec<u64> = live.iter().map(|(_, label)| label.id).collect();
+      assert_eq!(ids, [tagged[0].1, tagged[2].1, tagged[4].1, 0]);
+      assert_eq!(live[3].0.data_location().as_ptr(), untagged);
+      // plain allocs are labeled too, with id 0, freed ones aren't anymore
+      assert_eq!(myalloc.label_of(untagged).unwrap().id, 0);
+      assert_eq!(myalloc.label_of(tagged[1].0.as_ptr()), None);
+
+      myalloc.dealloc(untagged, LAY);
+      for i in [0, 2, 4]
+      {
+        myalloc.dealloc(tagged[i].0.as_ptr(), LAY);
+      }
+      myalloc.check_leaks();
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "1 allocations leaked")]
+  pub fn check_leaks_reports_live()
+  {
+    let myalloc = MetaAlloc::new();
+    myalloc.alloc_with_id(LAY).unwrap();
+    myalloc.check_leaks();
+  }
+
+  #[test]
+  pub fn no_overlap_after_workload()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      run_random_workload(&myalloc, 0x5EED, 5000);
+
+      let mut stored = Vec::new();
+      for i in 1..200
+      {
+        let lay = Layout::from_size_align(i, 1 << (i % 7)).unwrap();
+        stored.push((myalloc.alloc(lay), lay));
+      }
+      myalloc.assert_no_overlap();
+
+      stored.into_iter().for_each(|(x, lay)| {
+        myalloc.dealloc(x, lay);
+      });
+      myalloc.assert_no_overlap();
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "overlap")]
+  pub fn no_overlap_catches_injected_header()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let a = myalloc.alloc(LAY);
+      let b = myalloc.alloc(LAY);
+
+      // pretend b's block starts inside a
+      let node_a = raw_to_existing_node(a);
+      let node_b = raw_to_existing_node(b);
+      (*node_b.as_ptr()).elem_mut().base = (*node_a.as_ptr()).elem().base.byte_add(8);
+      myalloc.assert_no_overlap();
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "dealloc of interior or misaligned pointer")]
+  pub fn dealloc_interior_pointer()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let ptr = myalloc.alloc(LAY);
+      myalloc.dealloc(ptr.add(4), LAY);
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "dealloc of interior or misaligned pointer")]
+  pub fn dealloc_aligned_interior_pointer()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let ptr = myalloc.alloc(LAY);
+      // node aligned, so only the live map lookup tells it from a real data pointer
+      ptr.write_bytes(0xff, LAY.size());
+      myalloc.dealloc(ptr.add(NODE_ALIGN), LAY);
+    }
+  }
+
+  #[test]
+  pub fn distinct_fills()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let mut stored = Vec::new();
+      for i in 0..256
+      {
+        let lay = Layout::from_size_align(i % 97 + 1, 1 << (i % 8)).unwrap();
+        let ptr = myalloc.alloc(lay);
+        assert!(!ptr.is_null());
+        (*raw_to_existing_node(ptr).as_ptr())
+          .elem()
+          .fill_and_check(i as u8);
+        stored.push((ptr, lay));
+      }
+
+      for (i, (ptr, lay)) in stored.iter().enumerate()
+      {
+        let meta = (*raw_to_existing_node(*ptr).as_ptr()).elem();
+        let bytes = core::slice::from_raw_parts(*ptr, meta.usable_size());
+        assert!(bytes.iter().all(|b| *b == i as u8), "fill of {:?} overwritten", lay);
+      }
+
+      stored.into_iter().for_each(|(x, lay)| {
+        myalloc.dealloc(x, lay);
+      });
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  pub fn free_bytes_cap()
+  {
+    unsafe {
+      let cap = PAGE_SIZE * 2;
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_free_bytes(Some(cap));
+
+      let stored: Vec<_> = (0..ALLOC_COUNT).map(|_| myalloc.alloc(LAY)).collect();
+      for x in stored
+      {
+        myalloc.dealloc(x, LAY);
+        assert!(myalloc.free_bytes() <= cap);
+        myalloc.validate();
+      }
+      assert!(myalloc.stats().pages_released > 0);
+
+      let stored: Vec<_> = (0..ALLOC_COUNT).map(|_| myalloc.alloc(LAY)).collect();
+      assert!(stored.iter().all(|x| !x.is_null()));
+      stored.into_iter().for_each(|x| {
+        myalloc.dealloc(x, LAY);
+      });
+      assert!(myalloc.free_bytes() <= cap);
+    }
+  }
+
+  #[test]
+  pub fn slab_uniform_objects()
+  {
+    unsafe {
+      const COUNT: usize = 5000;
+      let lay = Layout::from_size_align(24, 8).unwrap();
+      let myalloc = MetaAlloc::slab(lay.size(), lay.align());
+
+      let stored: Vec<_> = (0..COUNT).map(|_| myalloc.alloc(lay)).collect();
+      assert!(stored.iter().all(|x| !x.is_null() && x.addr().is_multiple_of(lay.align())));
+      assert_eq!(
+        myalloc.stats().pages_committed,
+        COUNT.div_ceil(PAGE_SIZE / lay.size())
+      );
+
+      // the most recently freed cell is the next one handed out
+      myalloc.dealloc(stored[COUNT / 2], lay);
+      assert_eq!(myalloc.alloc(lay), stored[COUNT / 2]);
+
+      assert!(myalloc.alloc(LAY).is_null());
+
+      stored.into_iter().for_each(|x| {
+        myalloc.dealloc(x, lay);
+      });
+      let again: Vec<_> = (0..COUNT).map(|_| myalloc.alloc(lay)).collect();
+      again.into_iter().for_each(|x| {
+        myalloc.dealloc(x, lay);
+      });
+
+      let stats = myalloc.stats();
+      assert_eq!(stats.free_list_steps, 0);
+      assert_eq!(stats.pages_committed, COUNT.div_ceil(PAGE_SIZE / lay.size()));
+      assert_eq!(stats.bytes_in_use, 0);
+    }
+  }
+
+  #[test]
+  pub fn walk_heap_covers_pages()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let mut stored = Vec::new();
+      for i in 1..300
+      {
+        let lay = Layout::from_size_align(i * 3, 1 << (i % 6)).unwrap();
+        stored.push((myalloc.alloc(lay), lay));
+      }
+      for (x, lay) in stored.iter().step_by(3)
+      {
+        myalloc.dealloc(*x, *lay);
+      }
+
+      let blocks = myalloc.walk_heap();
+      assert!(blocks.iter().any(|b| b.allocated) && blocks.iter().any(|b| !b.allocated));
+      assert!(blocks[0].base.addr().get().is_multiple_of(PAGE_SIZE));
+      for pair in blocks.windows(2)
+      {
+        let end = pair[0].base.addr().get() + pair[0].size;
+        let next = pair[1].base.addr().get();
+        // either contiguous or the gap sits between two of our pages
+        assert!(
+          end == next
+            || (end.is_multiple_of(PAGE_SIZE) && next.is_multiple_of(PAGE_SIZE) && end < next)
+        );
+      }
+      let last = blocks.last().unwrap();
+      assert!((last.base.addr().get() + last.size).is_multiple_of(PAGE_SIZE));
+
+      let mut rev = myalloc.walk_heap_rev();
+      rev.reverse();
+      assert_eq!(rev, blocks);
+
+      let stats = myalloc.stats();
+      assert_eq!(
+        blocks.iter().map(|b| b.size).sum::<usize>(),
+        (stats.pages_committed - stats.pages_released) * PAGE_SIZE
+      );
+
+      for (x, lay) in stored.iter().skip(1).step_by(3)
+      {
+        myalloc.dealloc(*x, *lay);
+      }
+      for (x, lay) in stored.iter().skip(2).step_by(3)
+      {
+        myalloc.dealloc(*x, *lay);
+      }
+      assert!(myalloc.walk_heap().iter().all(|b| !b.allocated));
+    }
+  }
+
+  #[test]
+  pub fn over_alignment_pays_padding()
+  {
+    for offset in [0, 3, 8, 13]
+    {
+      let base =
+        NonNull::new(core::ptr::without_provenance_mut::<u8>(PAGE_SIZE * 4 + offset)).unwrap();
+      let small = MetaData::new(base, Layout::from_size_align(64, 8).unwrap());
+      let large = MetaData::new(base, Layout::from_size_align(64, PAGE_SIZE).unwrap());
+
+      assert!(small.total_size() - 64 < NODE_SIZE + NODE_ALIGN);
+      assert!(large.total_size() - 64 >= PAGE_SIZE);
+    }
+  }
+
+  #[test]
+  pub fn preserve_large_blocks()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_preserve_large(Some(PAGE_SIZE / 4));
+
+      let big_lay = Layout::from_size_align(PAGE_SIZE / 2 - NODE_SIZE, 8).unwrap();
+      let big = myalloc.alloc(big_lay);
+      let s1 = myalloc.alloc(LAY);
+      let hole = myalloc.alloc(LAY);
+      let s2 = myalloc.alloc(LAY);
+      myalloc.dealloc(big, big_lay);
+      myalloc.dealloc(hole, LAY);
+
+      let big_block = myalloc.free_blocks()[0].clone();
+      assert!(big_block.total_size() > PAGE_SIZE / 4);
+
+      // first fit alone would carve the big block at the lower address
+      assert_eq!(myalloc.alloc(LAY), hole);
+      assert_eq!(myalloc.free_blocks()[0], big_block);
+
+      // nothing small is left, so the big block is carved after all
+      let carved = myalloc.alloc(LAY);
+      let big_start = big_block.base.addr().get();
+      assert!((big_start..big_start + big_block.total_size()).contains(&carved.addr()));
+
+      for x in [s1, hole, s2, carved]
+      {
+        myalloc.dealloc(x, LAY);
+      }
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  pub fn pages_are_aligned()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let ptr = myalloc.alloc(LAY);
+      let index = page_index_of(ptr).unwrap();
+
+      let page = FAKE_HEAP.lock().unwrap().base.add(index * PAGE_SIZE);
+      assert!(page.addr().is_multiple_of(PAGE_SIZE));
+      assert!((page.addr()..page.addr() + PAGE_SIZE).contains(&ptr.addr()));
+      assert_eq!(page_index_of(core::ptr::null()), None);
+
+      myalloc.dealloc(ptr, LAY);
+    }
+  }
+
+  #[test]
+  pub fn avg_scan_length_first_fit()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let stored: Vec<_> = (0..5).map(|_| myalloc.alloc(LAY)).collect();
+      myalloc.dealloc(stored[0], LAY);
+      myalloc.dealloc(stored[2], LAY);
+      // free list is now [stored[0], stored[2], rest of the page]
+      assert_eq!(myalloc.avg_scan_length(), 0.0);
+      myalloc.set_scan_timing(true);
+
+      // passes both holes before the rest of the page fits
+      let big_lay = Layout::from_size_align(200, 8).unwrap();
+      let big = myalloc.alloc(big_lay);
+      // first hole fits right away
+      let small = myalloc.alloc(LAY);
+      assert_eq!(small, stored[0]);
+      assert_eq!(myalloc.avg_scan_length(), 2.0);
+
+      myalloc.set_scan_timing(false);
+      myalloc.dealloc(big, big_lay);
+      for x in [small, stored[1], stored[3], stored[4]]
+      {
+        myalloc.dealloc(x, LAY);
+      }
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  pub fn new_blank_undersized()
+  {
+    let mut buf = vec![0u8; PAGE_SIZE];
+    for offset in [0, 5]
+    {
+      let base = NonNull::from(&mut buf[offset]);
+      let total_removed = MetaData::default_meta_offset(base) + NODE_SIZE;
+
+      assert_eq!(MetaData::new_blank(base, total_removed - 1), None);
+      assert_eq!(MetaData::new_blank(base, 0), None);
+
+      let exact = MetaData::new_blank(base, total_removed).unwrap();
+      assert_eq!(exact.layout.size(), 0);
+      assert_eq!(exact.total_size(), total_removed);
+    }
+  }
+
+  #[test]
+  pub fn frozen_allocation_free_code()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let buf = myalloc.alloc(LAY);
+
+      myalloc.freeze();
+      for i in 0..LAY.size()
+      {
+        buf.add(i).write(i as u8);
+      }
+      myalloc.unfreeze();
+
+      myalloc.dealloc(buf, LAY);
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "allocation while frozen")]
+  pub fn frozen_growth_panics()
+  {
+    // a real Vec can't sit on a MetaAlloc here: Vec::with_capacity_in needs the unstable
+    // allocator_api and this crate builds on stable, and a test binary with a MetaAlloc
+    // #[global_allocator] would re-enter its own lock: the side tables are std collections that
+    // allocate through the global allocator while it is held. so this is Vec::push by hand,
+    // doubling the buffer through realloc once it is full the way RawVec grows
+    struct Growing<'a>
+    {
+      alloc: &'a MetaAlloc,
+      buf: *mut u64,
+      len: usize,
+      cap: usize,
+    }
+
+    impl Growing<'_>
+    {
+      unsafe fn push(&mut self, value: u64)
+      {
+        if self.len == self.cap
+        {
+          let old = Layout::array::<u64>(self.cap).unwrap();
+          let buf = unsafe { self.alloc.realloc(self.buf.cast(), old, old.size() * 2) };
+          assert!(!buf.is_null());
+          self.buf = buf.cast();
+          self.cap *= 2;
+        }
+        unsafe { self.buf.add(self.len).write(value) };
+        self.len += 1;
+      }
+    }
+
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let mut v = Growing {
+        alloc: &myalloc,
+        buf: myalloc.alloc(Layout::array::<u64>(4).unwrap()).cast(),
+        len: 0,
+        cap: 4,
+      };
+
+      myalloc.freeze();
+      // within capacity, nothing reaches the allocator
+      for i in 0..4
+      {
+        v.push(i);
+      }
+      // full, this one grows
+      v.push(4);
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "allocation while frozen")]
+  pub fn frozen_coalesce_owned_panics()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let a = myalloc.alloc(LAY);
+      let b = myalloc.alloc(LAY);
+      myalloc.freeze();
+      myalloc.coalesce_owned(a, b);
+    }
+  }
+
+  static OOM_ALLOC: MetaAlloc = MetaAlloc::new();
+  static EMERGENCY: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+  static OOM_CALLS: AtomicUsize = AtomicUsize::new(0);
+  const EMERGENCY_LAY: Layout = unsafe { Layout::from_size_align_unchecked(1024, 8) };
+
+  fn release_emergency(_: Layout) -> OomAction
+  {
+    OOM_CALLS.fetch_add(1, Ordering::Relaxed);
+    let reserve = EMERGENCY.swap(core::ptr::null_mut(), Ordering::Relaxed);
+    if reserve.is_null()
+    {
+      OomAction::Fail
+    }
+    else
+    {
+      unsafe { OOM_ALLOC.dealloc(reserve, EMERGENCY_LAY) };
+      OomAction::Retry
+    }
+  }
+
+  #[test]
+  pub fn oom_handler_retry()
+  {
+    unsafe {
+      OOM_ALLOC.set_max_pages(Some(1));
+      EMERGENCY.store(OOM_ALLOC.alloc(EMERGENCY_LAY), Ordering::Relaxed);
+
+      let mut stored = Vec::new();
+      loop
+      {
+        let ptr = OOM_ALLOC.alloc(LAY);
+        if ptr.is_null()
+        {
+          break;
+        }
+        stored.push(ptr);
+      }
+
+      OOM_ALLOC.set_oom_handler(release_emergency);
+      let lay = Layout::from_size_align(512, 8).unwrap();
+      let ptr = OOM_ALLOC.alloc(lay);
+      assert!(!ptr.is_null());
+      assert_eq!(OOM_CALLS.load(Ordering::Relaxed), 1);
+
+      // nothing left to release, the handler gives up
+      assert!(OOM_ALLOC.alloc(EMERGENCY_LAY).is_null());
+      assert_eq!(OOM_CALLS.load(Ordering::Relaxed), 2);
+
+      OOM_ALLOC.dealloc(ptr, lay);
+      stored.into_iter().for_each(|x| {
+        OOM_ALLOC.dealloc(x, LAY);
+      });
+      OOM_ALLOC.check_leaks();
+    }
+  }
+
+  #[test]
+  pub fn overhead_of_over_aligned()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let lay = Layout::from_size_align(64, 256).unwrap();
+
+      // fresh page, so the header rounds up to the alignment and nothing more
+      let first = myalloc.alloc(lay);
+      assert_eq!(myalloc.overhead_bytes(), 256);
+
+      // starts 320 bytes into the page, 192 more bytes to reach the next 256 boundary
+      let second = myalloc.alloc(lay);
+      assert_eq!(myalloc.overhead_bytes(), 256 + 256 + 192);
+      assert_eq!(myalloc.committed_bytes(), PAGE_SIZE);
+
+      myalloc.dealloc(first, lay);
+      assert_eq!(myalloc.overhead_bytes(), 256 + 192);
+      myalloc.dealloc(second, lay);
+      assert_eq!(myalloc.overhead_bytes(), 0);
+    }
+  }
+
+  #[test]
+  pub fn checkpoint_restore()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let kept = myalloc.alloc(LAY);
+      let cp = myalloc.checkpoint();
+      let free_then = myalloc.free_blocks();
+
+      let after = myalloc.alloc(LAY);
+      let big_lay = Layout::from_size_align(PAGE_SIZE * 2, 8).unwrap();
+      let big = myalloc.alloc(big_lay);
+      assert!(!big.is_null());
+      myalloc.dealloc(kept, LAY);
+
+      myalloc.restore(cp);
+      myalloc.validate();
+      assert_eq!(myalloc.free_blocks(), free_then);
+      let live = myalloc.live_allocations();
+      assert_eq!(live.len(), 1);
+      assert_eq!(live[0].0.data_location().as_ptr(), kept);
+      assert_eq!(myalloc.committed_bytes(), PAGE_SIZE);
+
+      // the region handed out after the checkpoint is free again
+      assert_eq!(myalloc.alloc(LAY), after);
+
+      myalloc.dealloc(after, LAY);
+      myalloc.dealloc(kept, LAY);
+      myalloc.check_leaks();
+    }
+  }
+
+  #[test]
+  pub fn size_not_multiple_of_align()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let lays = [
+        Layout::from_size_align(24, 16).unwrap(),
+        Layout::from_size_align(40, 32).unwrap(),
+        Layout::from_size_align(3, 2).unwrap(),
+        Layout::from_size_align(100, 64).unwrap(),
+      ];
+
+      let mut stored = Vec::new();
+      for i in 0..400
+      {
+        let lay = lays[i % lays.len()];
+        let ptr = myalloc.alloc(lay);
+        assert!(!ptr.is_null());
+        assert!(ptr.addr().is_multiple_of(lay.align()));
+        ptr.write_bytes(i as u8, lay.size());
+        stored.push((ptr, lay));
+      }
+      myalloc.assert_no_overlap();
+
+      for (i, (ptr, lay)) in stored.iter().enumerate()
+      {
+        let bytes = core::slice::from_raw_parts(*ptr, lay.size());
+        assert!(bytes.iter().all(|b| *b == i as u8), "{:?} overwritten", lay);
+      }
+
+      for (ptr, lay) in stored.iter().step_by(2).chain(stored.iter().skip(1).step_by(2))
+      {
+        myalloc.dealloc(*ptr, *lay);
+        myalloc.validate();
+      }
+    }
+  }
+
+  #[test]
+  pub fn metadata_for_live_blocks()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let other = MetaAlloc::new();
+      let lay = Layout::from_size_align(24, 16).unwrap();
+      let a = myalloc.alloc(lay);
+      let b = myalloc.alloc(LAY);
+      let foreign = other.alloc(LAY);
+
+      let meta = myalloc.metadata_for(a).unwrap();
+      assert_eq!(meta.layout.align(), lay.align());
+      assert!(meta.layout.size() >= lay.size());
+      assert_eq!(meta.data_location().as_ptr(), a);
+      assert_eq!(myalloc.metadata_for(b).unwrap().layout.align(), LAY.align());
+
+      assert!(myalloc.owns(a));
+      assert!(!myalloc.owns(a.add(4)));
+      assert!(!myalloc.owns(foreign));
+
+      myalloc.dealloc(a, lay);
+      assert_eq!(myalloc.metadata_for(a), None);
+
+      myalloc.dealloc(b, LAY);
+      other.dealloc(foreign, LAY);
+    }
+  }
+
+  // runs the fifo and lifo patterns against a, returns bytes in use at both peaks
+  unsafe fn fifo_lifo_peaks(a: &dyn GlobalAlloc, in_use: impl Fn() -> usize) -> [usize; 2]
+  {
+    unsafe {
+      let fifo: Vec<_> = (1..ALLOC_COUNT)
+        .map(|i| (a.alloc(Layout::from_size_align(i, 8).unwrap()), i))
+        .collect();
+      assert!(fifo.iter().all(|(x, _)| !x.is_null()));
+      let fifo_peak = in_use();
+      for (x, i) in fifo
+      {
+        a.dealloc(x, Layout::from_size_align(i, 8).unwrap());
+      }
+
+      let lifo: Vec<_> = (0..ALLOC_COUNT).map(|_| a.alloc(LAY)).collect();
+      assert!(lifo.iter().all(|x| !x.is_null()));
+      let lifo_peak = in_use();
+      for x in lifo.into_iter().rev()
+      {
+        a.dealloc(x, LAY);
+      }
+
+      [fifo_peak, lifo_peak]
+    }
+  }
+
+  #[test]
+  pub fn local_matches_locked()
+  {
+    unsafe {
+      let locked = MetaAlloc::new();
+      let local = MetaAllocLocal::new();
+      assert_eq!(
+        fifo_lifo_peaks(&locked, || locked.stats().bytes_in_use),
+        fifo_lifo_peaks(&local, || local.stats().bytes_in_use)
+      );
+      locked.validate();
+      local.validate();
+
+      let (locked, local) = (locked.stats(), local.stats());
+      assert_eq!(locked.allocations, local.allocations);
+      assert_eq!(locked.deallocations, local.deallocations);
+      assert_eq!(local.bytes_in_use, 0);
+    }
+  }
+
+  #[test]
+  pub fn dealloc_batch_single_lock()
+  {
+    unsafe {
+      const COUNT: usize = 10_000;
+      let myalloc = MetaAlloc::new();
+      let stored: Vec<_> = (0..COUNT).map(|_| myalloc.alloc(LAY)).collect();
+
+      let before = myalloc.stats();
+      myalloc.dealloc_batch(&stored);
+      let delta = myalloc.stats().delta(&before);
+
+      assert_eq!(delta.deallocations, COUNT);
+      assert_eq!(delta.lock_acquisitions, 1);
+      assert_eq!(myalloc.stats().bytes_in_use, 0);
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  pub fn alloc_fill_pattern()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::with_alloc_fill(0xAB);
+      let lay = Layout::from_size_align(64, 8).unwrap();
+
+      let ptr = myalloc.alloc(lay);
+      let bytes = core::slice::from_raw_parts(ptr, lay.size());
+      assert!(bytes.iter().all(|b| *b == 0xAB));
+
+      // a freed and reused block is filled again
+      ptr.write_bytes(0x11, lay.size());
+      myalloc.dealloc(ptr, lay);
+      let again = myalloc.alloc(lay);
+      assert_eq!(again, ptr);
+      assert_eq!(*again, 0xAB);
+
+      let zeroed = myalloc.alloc_zeroed(lay);
+      let bytes = core::slice::from_raw_parts(zeroed, lay.size());
+      assert!(bytes.iter().all(|b| *b == 0));
+
+      myalloc.dealloc(again, lay);
+      myalloc.dealloc(zeroed, lay);
+    }
+  }
+
+  #[test]
+  pub fn max_allocatable_without_commit()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      assert_eq!(myalloc.max_allocatable(), 0);
+      myalloc.set_max_pages(Some(1));
+
+      let a = myalloc.alloc(LAY);
+      let big_lay = Layout::from_size_align(2000, NODE_ALIGN).unwrap();
+      let big = myalloc.alloc(big_lay);
+      let c = myalloc.alloc(LAY);
+
+      let tail_lay = Layout::from_size_align(myalloc.max_allocatable(), NODE_ALIGN).unwrap();
+      let (tail, committed) = myalloc.alloc_traced(tail_lay);
+      assert!(!committed);
+      assert_eq!(myalloc.max_allocatable(), 0);
+
+      let usable = myalloc.metadata_for(big).unwrap().usable_size();
+      myalloc.dealloc(big, big_lay);
+      assert_eq!(myalloc.max_allocatable(), usable);
+
+      let fit_lay = Layout::from_size_align(usable, NODE_ALIGN).unwrap();
+      let (fit, committed) = myalloc.alloc_traced(fit_lay);
+      assert!(!committed);
+      assert_eq!(fit.unwrap().as_ptr(), big);
+
+      myalloc.dealloc(fit.unwrap().as_ptr(), fit_lay);
+      myalloc.dealloc(tail.unwrap().as_ptr(), tail_lay);
+      myalloc.dealloc(a, LAY);
+      myalloc.dealloc(c, LAY);
+      myalloc.check_leaks();
+    }
+  }
+
+  #[test]
+  pub fn metadata_hash_set()
+  {
+    let buf = [0u8; 64];
+    let base = NonNull::from(&buf[0]);
+    let other = NonNull::from(&buf[8]);
+    let lay = Layout::from_size_align(16, 8).unwrap();
+
+    let set: HashSet<MetaData> = [
+      MetaData::new(base, lay),
+      MetaData::new(base, lay),
+      MetaData::new(other, lay),
+      MetaData::new(base, LAY),
+      MetaData::new(other, lay),
+    ]
+    .into_iter()
+    .collect();
+
+    assert_eq!(set.len(), 3);
+    assert!(set.contains(&MetaData::new(base, LAY)));
+  }
+
+  #[test]
+  pub fn free_gaps_layout()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      let stored: Vec<_> = (0..4).map(|_| myalloc.alloc(LAY)).collect();
+      myalloc.dealloc(stored[0], LAY);
+      myalloc.dealloc(stored[2], LAY);
+
+      // [free, live, free, live, free tail]
+      let block = myalloc.metadata_for(stored[1]).unwrap().total_size();
+      // a freed block is a blank header of NODE_ALIGN, its node isn't rounded up to LAY's align
+      let gaps = myalloc.free_gaps();
+      assert_eq!(gaps.len(), 3);
+      assert_eq!(gaps[0].1, block - NODE_SIZE);
+      assert_eq!(gaps[0].2, block);
+      assert_eq!(gaps[1].1, block - NODE_SIZE);
+      assert_eq!(gaps[1].2, block);
+      assert_eq!(gaps[2].0.as_ptr(), stored[3].add(LAY.size()));
+      assert_eq!(gaps[2].2, 0);
+
+      myalloc.dealloc(stored[1], LAY);
+      myalloc.dealloc(stored[3], LAY);
+      assert_eq!(myalloc.free_gaps().len(), 1);
+    }
+  }
+
+  #[test]
+  pub fn oldest_live_by_seq()
+  {
+    unsafe {
+      // plain allocs on a default allocator, every live allocation has a seq
+      let myalloc = MetaAlloc::new();
+      assert_eq!(myalloc.oldest_live_allocation(), None);
+
+      let stored: Vec<_> = (0..6).map(|_| myalloc.alloc(LAY)).collect();
+      let seqs: Vec<u64> = stored
+        .iter()
+        .map(|x| myalloc.label_of(*x).unwrap().seq)
+        .collect();
+      assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+
+      // free the oldest and some newer ones, stored[1] is the oldest survivor
+      for i in [0, 3, 5]
+      {
+        myalloc.dealloc(stored[i], LAY);
+      }
+      let (ptr, seq) = myalloc.oldest_live_allocation().unwrap();
+      assert_eq!(ptr.as_ptr(), stored[1]);
+      assert_eq!(seq, seqs[1]);
+
+      // a block reused later is young again
+      let reused = myalloc.alloc(LAY);
+      assert_eq!(reused, stored[0]);
+      assert_eq!(myalloc.oldest_live_allocation().unwrap().0.as_ptr(), stored[1]);
+
+      for x in [reused, stored[1], stored[2], stored[4]]
+      {
+        myalloc.dealloc(x, LAY);
+      }
+    }
+  }
+
+  #[test]
+  pub fn bytes_by_tag_totals()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let small = Layout::from_size_align(16, 8).unwrap();
+      let large = Layout::from_size_align(200, 8).unwrap();
+
+      let a: Vec<_> = (0..4)
+        .map(|_| myalloc.alloc_tagged(small, 1).unwrap())
+        .collect();
+      let b: Vec<_> = (0..3)
+        .map(|_| myalloc.alloc_tagged(large, 2).unwrap())
+        .collect();
+      let untagged = myalloc.alloc(small);
+
+      myalloc.dealloc(a[0].as_ptr(), small);
+      myalloc.dealloc(b[1].as_ptr(), large);
+
+      let usable = |ptr: NonNull<u8>| myalloc.metadata_for(ptr.as_ptr()).unwrap().usable_size();
+      let by_tag = myalloc.bytes_by_tag();
+      assert_eq!(by_tag[&1], a[1..].iter().map(|x| usable(*x)).sum::<usize>());
+      assert_eq!(by_tag[&2], usable(b[0]) + usable(b[2]));
+      assert_eq!(by_tag[&0], usable(NonNull::new(untagged).unwrap()));
+      assert_eq!(by_tag.values().sum::<usize>(), myalloc.stats().bytes_in_use);
+
+      for x in &a[1..]
+      {
+        myalloc.dealloc(x.as_ptr(), small);
+      }
+      myalloc.dealloc(b[0].as_ptr(), large);
+      myalloc.dealloc(b[2].as_ptr(), large);
+      myalloc.dealloc(untagged, small);
+      assert!(myalloc.bytes_by_tag().is_empty());
+    }
+  }
+
+  #[test]
+  pub fn alloc_dealloc_is_identity()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      let first = myalloc.alloc(LAY);
+      myalloc.dealloc(first, LAY);
+
+      let original = myalloc.free_blocks();
+      assert_eq!(original.len(), 1);
+      assert_eq!(original[0].total_size(), PAGE_SIZE);
+
+      let lays = [
+        (1, 1),
+        (24, 16),
+        (40, 32),
+        (100, 64),
+        (1000, 256),
+        (3000, 8),
+        (PAGE_SIZE / 2, PAGE_SIZE / 4),
+        (original[0].usable_size(), NODE_ALIGN),
+      ];
+      for (size, align) in lays
+      {
+        let lay = Layout::from_size_align(size, align).unwrap();
+        let ptr = myalloc.alloc(lay);
+        assert!(!ptr.is_null(), "{:?} did not fit a fresh page", lay);
+        myalloc.dealloc(ptr, lay);
+        assert_eq!(myalloc.free_blocks(), original, "{:?} changed the free block", lay);
+      }
+    }
+  }
+
+  #[test]
+  pub fn contiguous_free_runs_match_free_blocks()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let runs_as_blocks = || {
+        myalloc
+          .free_blocks()
+          .iter()
+          .map(|meta| (meta.base, meta.total_size()))
+          .collect::<Vec<_>>()
+      };
+
+      let a = myalloc.alloc(LAY);
+      let b = myalloc.alloc(LAY);
+      // a, b and the rest of the page end up as three unmerged free blocks, one physical run
+      {
+        let mut inner = myalloc.tex.lock().unwrap();
+        let node_a = inner.untrack(a).unwrap();
+        let node_b = inner.untrack(b).unwrap();
+        inner.list.push_front(node_b);
+        inner.list.push_front(node_a);
+      }
+      let first = myalloc.free_blocks()[0].clone();
+      assert_eq!(myalloc.free_blocks().len(), 3);
+      assert_eq!(myalloc.contiguous_free_runs(), [(first.base, PAGE_SIZE)]);
+
+      myalloc.coalesce_free();
+      assert_eq!(myalloc.contiguous_free_runs(), runs_as_blocks());
+
+      let mut stored = Vec::new();
+      for i in 1..200
+      {
+        let lay = Layout::from_size_align(i * 5, 1 << (i % 5)).unwrap();
+        stored.push((myalloc.alloc(lay), lay));
+      }
+      for (x, lay) in stored.iter().step_by(2)
+      {
+        myalloc.dealloc(*x, *lay);
+      }
+      myalloc.coalesce_free();
+      assert_eq!(myalloc.contiguous_free_runs(), runs_as_blocks());
+
+      for (x, lay) in stored.iter().skip(1).step_by(2)
+      {
+        myalloc.dealloc(*x, *lay);
+      }
+      assert_eq!(myalloc.contiguous_free_runs(), runs_as_blocks());
+    }
+  }
+
+  fn no_alloc_on_free_path(lay: Layout) -> OomAction
+  {
+    panic!("the free path tried to allocate {:?} from a full heap", lay);
+  }
+
+  // dealloc only relinks headers that already exist, so it has to work on a heap with no room left
+  // and must never allocate or commit a page on its own
+  #[test]
+  pub fn dealloc_into_full_heap_never_allocates()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(2));
+      let mut rng = Rng::new(0xF00D);
+      let mut stored = Vec::new();
+      loop
+      {
+        let lay = Layout::from_size_align(8 + rng.below(200), 1 << rng.below(7)).unwrap();
+        let ptr = myalloc.alloc(lay);
+        if ptr.is_null()
+        {
+          break;
+        }
+        stored.push((ptr, lay));
+      }
+      // top off with the smallest blocks so nothing is left to hand out
+      while let Some(ptr) = NonNull::new(myalloc.alloc(Layout::new::<u8>()))
+      {
+        stored.push((ptr.as_ptr(), Layout::new::<u8>()));
+      }
+      assert_eq!(myalloc.max_allocatable(), 0);
+
+      myalloc.set_oom_handler(no_alloc_on_free_path);
+      let before = myalloc.stats();
+      for i in (1..stored.len()).rev()
+      {
+        stored.swap(i, rng.below(i + 1));
+      }
+      for (count, (ptr, lay)) in stored.iter().enumerate()
+      {
+        myalloc.dealloc(*ptr, *lay);
+        let delta = myalloc.stats().delta(&before);
+        assert_eq!(delta.allocations, 0);
+        assert_eq!(delta.pages_committed, 0);
+        assert_eq!(delta.deallocations, count + 1);
+      }
+      assert_eq!(myalloc.stats().bytes_in_use, 0);
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  pub fn coalesce_owned_adjacent_blocks()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      // a fresh page is carved front to back, so a and b are neighbours
+      let a = myalloc.alloc(LAY);
+      let b = myalloc.alloc_located(LAY).unwrap().as_ptr();
+      let block_b = myalloc.metadata_for(b).unwrap();
+      let usable_a = myalloc.metadata_for(a).unwrap().usable_size();
+      assert_eq!(myalloc.coalesce_owned(b, a), None);
+
+      let before = myalloc.stats();
+      assert_eq!(myalloc.coalesce_owned(a, b), Some(a));
+      assert!(!myalloc.owns(b));
+      assert_eq!(myalloc.location_of(b), None);
+      let combined = myalloc.metadata_for(a).unwrap();
+      assert_eq!(combined.usable_size(), usable_a + block_b.total_size());
+      assert_eq!(
+        combined.base.addr().get() + combined.total_size(),
+        block_b.base.addr().get() + block_b.total_size()
+      );
+      let stats = myalloc.stats();
+      assert_eq!(stats.deallocations, before.deallocations + 1);
+      assert_eq!(stats.bytes_in_use, combined.usable_size());
+
+      for i in 0..combined.usable_size()
+      {
+        a.add(i).write(i as u8);
+      }
+      for i in 0..combined.usable_size()
+      {
+        assert_eq!(a.add(i).read(), i as u8);
+      }
+
+      // wrong order, different alignment and not adjacent all leave both blocks alone
+      let wide = Layout::from_size_align(32, 64).unwrap();
+      let c = myalloc.alloc(LAY);
+      let d = myalloc.alloc(wide);
+      assert_eq!(myalloc.coalesce_owned(c, d), None);
+      assert_eq!(myalloc.coalesce_owned(a, d), None);
+      assert_eq!(myalloc.coalesce_owned(a, b), None);
+      assert!(myalloc.owns(c) && myalloc.owns(d));
+
+      myalloc.dealloc(a, LAY);
+      myalloc.dealloc(c, LAY);
+      myalloc.dealloc(d, wide);
+      assert_eq!(myalloc.stats().bytes_in_use, 0);
+      myalloc.validate();
+      assert_eq!(myalloc.free_blocks().len(), 1);
+      assert_eq!(myalloc.free_blocks()[0].total_size(), PAGE_SIZE);
+    }
+  }
+
+  // Layout caps sizes at isize::MAX, so size plus header and padding stays far from usize::MAX
+  #[test]
+  pub fn huge_layout_sizes_do_not_wrap()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      let ptr = myalloc.alloc(LAY);
+      myalloc.dealloc(ptr, LAY);
+      let page = myalloc.free_blocks()[0].clone();
+
+      for align in [1, NODE_ALIGN, PAGE_SIZE, 1 << 30]
+      {
+        let huge = Layout::from_size_align(isize::MAX as usize - (align - 1), align).unwrap();
+        let meta = MetaData::new(page.base, huge);
+        assert!(meta.total_size() > huge.size());
+        assert_eq!(meta.usable_size(), huge.size());
+        assert!(!page.check_compatible(&huge));
+        assert!(myalloc.alloc(huge).is_null());
+      }
+      assert_eq!(myalloc.stats().pages_committed, 1);
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  pub fn unsatisfiable_size_commits_nothing()
+  {
+    // no page cap, only the size of the fake heap stops these
+    let myalloc = MetaAlloc::new();
+    assert_eq!(
+      myalloc.alloc_checked(FAKE_HEAP_SIZE, NODE_ALIGN),
+      Err(AllocError::OutOfMemory)
+    );
+    assert_eq!(
+      myalloc.alloc_checked(isize::MAX as usize - (PAGE_SIZE - 1), PAGE_SIZE),
+      Err(AllocError::OutOfMemory)
+    );
+    assert!(
+      unsafe { myalloc.alloc(Layout::from_size_align(FAKE_HEAP_SIZE, 1).unwrap()) }.is_null()
+    );
+    assert_eq!(myalloc.stats().pages_committed, 0);
+    assert!(myalloc.segments().is_empty());
+  }
+
+  #[test]
+  pub fn fragmentation_classes()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      assert!(myalloc.fragmentation_by_class().is_empty());
+      myalloc.set_max_pages(Some(1));
+
+      // free blocks of 96, 160, 160 and 512 bytes, each kept apart by a live LAY block
+      let mut freed = Vec::new();
+      let mut kept = Vec::new();
+      let node = NODE_SIZE.next_multiple_of(16);
+      for total in [96, 160, 160, 512]
+      {
+        let lay = Layout::from_size_align(total - node, 16).unwrap();
+        let ptr = myalloc.alloc(lay);
+        assert_eq!(myalloc.metadata_for(ptr).unwrap().total_size(), total);
+        freed.push((ptr, lay));
+        kept.push(myalloc.alloc(LAY));
+      }
+      for (ptr, lay) in freed
+      {
+        myalloc.dealloc(ptr, lay);
+      }
+
+      let tail = myalloc.free_blocks().last().unwrap().total_size();
+      assert!((PAGE_SIZE / 2..PAGE_SIZE).contains(&tail));
+      assert_eq!(
+        myalloc.fragmentation_by_class(),
+        [(64, 1), (128, 2), (512, 1), (PAGE_SIZE / 2, 1)]
+      );
+
+      for ptr in kept
+      {
+        myalloc.dealloc(ptr, LAY);
+      }
+      assert_eq!(myalloc.fragmentation_by_class(), [(PAGE_SIZE, 1)]);
+    }
+  }
+
+  #[test]
+  pub fn report_matches_walk_heap()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let report = myalloc.report();
+      assert!(report.blocks.is_empty());
+      assert_eq!(report.stats, myalloc.stats());
+
+      let mut stored = Vec::new();
+      for i in 1..100
+      {
+        let lay = Layout::from_size_align(i * 7, 1 << (i % 7)).unwrap();
+        stored.push((myalloc.alloc(lay), lay));
+      }
+      for (x, lay) in stored.iter().step_by(3)
+      {
+        myalloc.dealloc(*x, *lay);
+      }
+
+      let report = myalloc.report();
+      assert_eq!(report.page_size, PAGE_SIZE);
+      assert_eq!(report.blocks, myalloc.walk_heap());
+      assert_eq!(report.stats, myalloc.stats());
+      assert_eq!(
+        report.blocks.iter().map(|b| b.size).sum::<usize>(),
+        (report.stats.pages_committed - report.stats.pages_released) * report.page_size
+      );
+      assert_eq!(
+        report.blocks.iter().filter(|b| b.allocated).count(),
+        report.stats.allocations - report.stats.deallocations
+      );
+
+      for (x, lay) in stored.iter().skip(1).step_by(3)
+      {
+        myalloc.dealloc(*x, *lay);
+      }
+      for (x, lay) in stored.iter().skip(2).step_by(3)
+      {
+        myalloc.dealloc(*x, *lay);
+      }
+    }
+  }
+
+  #[test]
+  pub fn live_blocks_within_committed()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.assert_within_committed();
+
+      // larger than a page, so at least these have to span a page boundary
+      let big = Layout::from_size_align(PAGE_SIZE + PAGE_SIZE / 2, 8).unwrap();
+      let mut stored = vec![(myalloc.alloc(big), big)];
+      for i in 1..200
+      {
+        let lay = Layout::from_size_align(i * 11, 1 << (i % 6)).unwrap();
+        stored.push((myalloc.alloc(lay), lay));
+        myalloc.assert_within_committed();
+      }
+      stored.push((myalloc.alloc(big), big));
+      assert!(myalloc.stats().pages_committed > 2);
+      myalloc.assert_within_committed();
+
+      for (x, lay) in stored
+      {
+        myalloc.dealloc(x, lay);
+      }
+      myalloc.assert_within_committed();
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "is outside the committed heap")]
+  pub fn within_committed_catches_overlong_block()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let a = myalloc.alloc(LAY);
+
+      // pretend a's layout runs far past anything the fake heap will ever hand out
+      let node_a = raw_to_existing_node(a);
+      (*node_a.as_ptr()).elem_mut().layout = Layout::from_size_align(usize::MAX / 4, 16).unwrap();
+      myalloc.assert_within_committed();
+    }
+  }
+
+  #[test]
+  pub fn merges_performed_counts_merges()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      let blocks: Vec<_> = (0..4).map(|_| myalloc.alloc(LAY)).collect();
+      let before = myalloc.merges_performed();
+
+      // a and c have live neighbours on both sides, b then joins a and c, d joins a..c and the
+      // free rest of the page
+      let expected = [0, 0, 2, 2];
+      for (i, idx) in [0, 2, 1, 3].into_iter().enumerate()
+      {
+        myalloc.dealloc(blocks[idx], LAY);
+        assert_eq!(
+          myalloc.merges_performed() - before,
+          expected[..=i].iter().sum::<u64>()
+        );
+      }
+      assert_eq!(myalloc.free_blocks().len(), 1);
+      assert_eq!(myalloc.stats().merges_performed as u64, myalloc.merges_performed());
+    }
+  }
+
+  #[test]
+  pub fn last_freed_cache_skips_scan()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      // holes too small for LAY in front of the free rest, so a real scan has something to walk
+      let small = Layout::from_size_align(16, 16).unwrap();
+      let kept: Vec<_> = (0..8).map(|_| myalloc.alloc(small)).collect();
+      for x in kept.iter().step_by(2)
+      {
+        myalloc.dealloc(*x, small);
+      }
+
+      myalloc.set_last_freed_cache(true);
+      let first = myalloc.alloc(LAY);
+      myalloc.dealloc(first, LAY);
+      for _ in 0..100
+      {
+        let steps = myalloc.stats().free_list_steps;
+        let ptr = myalloc.alloc(LAY);
+        assert_eq!(ptr, first);
+        assert_eq!(myalloc.stats().free_list_steps, steps);
+        myalloc.dealloc(ptr, LAY);
+      }
+
+      // a different layout flushes the cached block and scans as usual
+      let steps = myalloc.stats().free_list_steps;
+      let other = myalloc.alloc(Layout::from_size_align(48, 16).unwrap());
+      assert!(myalloc.stats().free_list_steps > steps);
+      myalloc.dealloc(other, Layout::from_size_align(48, 16).unwrap());
+
+      myalloc.set_last_freed_cache(false);
+      for x in kept.iter().skip(1).step_by(2)
+      {
+        myalloc.dealloc(*x, small);
+      }
+      assert_eq!(myalloc.stats().bytes_in_use, 0);
+      myalloc.validate();
+      assert_eq!(myalloc.free_blocks().len(), 1);
+      assert_eq!(myalloc.free_blocks()[0].total_size(), PAGE_SIZE);
+    }
+  }
+
+  #[test]
+  pub fn last_freed_cache_matches_requested_layout()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      myalloc.dealloc(myalloc.alloc(LAY), LAY);
+
+      // leaves 16 bytes at the end of the page, too few for a header, so they become slack
+      let lay = Layout::from_size_align(myalloc.max_allocatable() - 16, NODE_ALIGN).unwrap();
+      let ptr = myalloc.alloc(lay);
+      assert_eq!(myalloc.internal_fragmentation(), 16);
+
+      myalloc.set_last_freed_cache(true);
+      myalloc.dealloc(ptr, lay);
+      let steps = myalloc.stats().free_list_steps;
+      assert_eq!(myalloc.alloc(lay), ptr);
+      assert_eq!(myalloc.stats().free_list_steps, steps);
+      assert_eq!(myalloc.internal_fragmentation(), 16);
+
+      myalloc.dealloc(ptr, lay);
+      myalloc.set_last_freed_cache(false);
+      myalloc.validate();
+      assert_eq!(myalloc.free_bytes(), PAGE_SIZE);
+    }
+  }
+
+  #[test]
+  pub fn free_size_distribution_counts()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      assert!(myalloc.free_size_distribution().is_empty());
+      myalloc.set_max_pages(Some(1));
+
+      let mut freed = Vec::new();
+      let mut kept = Vec::new();
+      // NODE_ALIGN blocks keep their usable size once freed as blank blocks
+      for size in [32, 32, 64, 32]
+      {
+        let lay = Layout::from_size_align(size, NODE_ALIGN).unwrap();
+        freed.push((myalloc.alloc(lay), lay));
+        kept.push((myalloc.alloc(LAY), LAY));
+      }
+      // take the rest of the page so only the blocks freed below are left
+      let rest = Layout::from_size_align(myalloc.max_allocatable(), NODE_ALIGN).unwrap();
+      kept.push((myalloc.alloc(rest), rest));
+      assert!(myalloc.free_blocks().is_empty());
+
+      for (ptr, lay) in freed
+      {
+        myalloc.dealloc(ptr, lay);
+      }
+      assert_eq!(
+        myalloc.free_size_distribution(),
+        BTreeMap::from([(32, 3), (64, 1)])
+      );
+
+      for (ptr, lay) in kept
+      {
+        myalloc.dealloc(ptr, lay);
+      }
+      assert_eq!(
+        myalloc.free_size_distribution(),
+        BTreeMap::from([(PAGE_SIZE - NODE_SIZE, 1)])
+      );
+    }
+  }
+
+  #[test]
+  pub fn realloc_to_larger_alignment()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      let wide = Layout::from_size_align(32, 64).unwrap();
+      // the node in front of a 32 aligned block is rounded up to 64 bytes, the same as wide's
+      let narrow = Layout::from_size_align(32, 32).unwrap();
+
+      // the first block of a page has its data 64 aligned already, so it is re-homed in place
+      let a = myalloc.alloc(narrow);
+      let b = myalloc.alloc(narrow);
+      assert_eq!(a.addr() % 64, 0);
+      assert_ne!(b.addr() % 64, 0);
+      for (i, x) in [a, b].into_iter().enumerate()
+      {
+        x.write_bytes(0xA0 + i as u8, narrow.size());
+      }
+
+      let usable = myalloc.metadata_for(a).unwrap().usable_size();
+      let before = myalloc.snapshot();
+      let a2 = myalloc.realloc_aligned(a, narrow, wide);
+      assert_eq!(a2, a);
+      let meta = myalloc.metadata_for(a2).unwrap();
+      assert_eq!(meta.layout.align(), 64);
+      assert_eq!(meta.usable_size(), usable);
+      // in place still counts as a move for the stats
+      let delta = myalloc.snapshot().delta(&before);
+      assert_eq!((delta.stats.allocations, delta.stats.deallocations), (1, 1));
+      assert_eq!(delta.align_counts[wide.align().trailing_zeros() as usize], 1);
+      assert_eq!(
+        myalloc.stats().bytes_in_use,
+        myalloc
+          .live_allocations()
+          .iter()
+          .map(|(meta, _)| meta.usable_size())
+          .sum::<usize>()
+      );
+
+      let b2 = myalloc.realloc_aligned(b, narrow, wide);
+      assert_ne!(b2, b);
+      assert!(!myalloc.owns(b));
+      for (i, x) in [a2, b2].into_iter().enumerate()
+      {
+        assert_eq!(x.addr() % 64, 0);
+        assert!((0..narrow.size()).all(|j| x.add(j).read() == 0xA0 + i as u8));
+      }
+
+      myalloc.dealloc(a2, wide);
+      myalloc.dealloc(b2, wide);
+      assert_eq!(myalloc.stats().bytes_in_use, 0);
+      myalloc.validate();
+      assert_eq!(myalloc.free_blocks().len(), 1);
+    }
+  }
+
+  #[test]
+  pub fn no_merge_across_segments()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let other = MetaAlloc::new();
+      assert!(myalloc.segments().is_empty());
+
+      // every block fills a whole page, and other commits a page in between each of them, so
+      // myalloc's pages can't all be contiguous
+      let whole = Layout::from_size_align(PAGE_SIZE - NODE_SIZE, NODE_ALIGN).unwrap();
+      let mut stored = Vec::new();
+      let mut foreign = Vec::new();
+      while myalloc.segments().len() < 2
+      {
+        assert!(stored.len() < 64, "never got two segments");
+        stored.push(myalloc.alloc(whole));
+        foreign.push(other.alloc(whole));
+      }
+      let segments = myalloc.segments();
+      assert_eq!(
+        segments.iter().map(|seg| seg.1).sum::<usize>(),
+        stored.len() * PAGE_SIZE
+      );
+
+      for x in stored
+      {
+        myalloc.dealloc(x, whole);
+      }
+      myalloc.validate();
+      // one free block per segment, nothing merged over the foreign pages in between
+      let blocks: Vec<_> = myalloc
+        .free_blocks()
+        .iter()
+        .map(|meta| (meta.base, meta.total_size()))
+        .collect();
+      assert_eq!(blocks, segments);
+      assert_eq!(myalloc.contiguous_free_runs(), segments);
+
+      for x in foreign
+      {
+        other.dealloc(x, whole);
+      }
+    }
+  }
+
+  #[test]
+  pub fn fail_after_injects_oom()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let warm = myalloc.alloc(LAY);
+      myalloc.set_fail_after(Some(3));
+
+      let ptrs: Vec<_> = (0..4).map(|_| myalloc.alloc(LAY)).collect();
+      assert!(ptrs[..3].iter().all(|x| !x.is_null()));
+      assert!(ptrs[3].is_null());
+      assert!(myalloc.alloc(Layout::new::<u8>()).is_null());
+      assert!(myalloc.max_allocatable() > LAY.size());
+      assert_eq!(myalloc.stats().allocations, 4);
+
+      myalloc.set_fail_after(None);
+      let again = myalloc.alloc(LAY);
+      assert!(!again.is_null());
+
+      for x in ptrs[..3].iter().chain([&warm, &again])
+      {
+        myalloc.dealloc(*x, LAY);
+      }
+      assert_eq!(myalloc.stats().bytes_in_use, 0);
+    }
+  }
+
+  #[test]
+  pub fn internal_fragmentation_from_folded_leftovers()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      let mut stored = Vec::new();
+      for size in [1, 33, 77, 130]
+      {
+        let lay = Layout::from_size_align(size, 16).unwrap();
+        stored.push((myalloc.alloc(lay), lay));
+      }
+      // every block so far was carved exactly, the rest of the page stays free
+      assert_eq!(myalloc.internal_fragmentation(), 0);
+
+      // leaves 41 bytes at the end of the page, too few for a header, so they are folded in
+      let odd = Layout::from_size_align(myalloc.max_allocatable() - 41, NODE_ALIGN).unwrap();
+      let ptr = myalloc.alloc(odd);
+      stored.push((ptr, odd));
+      assert!(myalloc.free_blocks().is_empty());
+      let meta = myalloc.metadata_for(ptr).unwrap();
+      assert_eq!(meta.usable_size(), odd.size() + 41);
+      assert_eq!(myalloc.internal_fragmentation(), 41);
+
+      myalloc.dealloc(ptr, odd);
+      assert_eq!(myalloc.internal_fragmentation(), 0);
+      for (x, lay) in stored.iter().take(4)
+      {
+        myalloc.dealloc(*x, *lay);
+      }
+    }
+  }
+
+  #[test]
+  pub fn fits_count_matches_allocations()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      let first = myalloc.alloc(LAY);
+      myalloc.dealloc(first, LAY);
+      assert_eq!(myalloc.free_blocks().len(), 1);
+
+      // whatever is left of the page after the last whole block is no room for another header
+      // and gets folded into that block. LAY's node is rounded up to its 16 alignment
+      let lay64 = Layout::from_size_align(64, 8).unwrap();
+      assert_eq!(myalloc.fits_count(lay64), PAGE_SIZE / (64 + NODE_SIZE));
+      assert_eq!(
+        myalloc.fits_count(LAY),
+        PAGE_SIZE / (LAY.size() + NODE_SIZE.next_multiple_of(LAY.align()))
+      );
+      assert_eq!(myalloc.fits_count(Layout::from_size_align(PAGE_SIZE, 8).unwrap()), 0);
+
+      for lay in [lay64, LAY, Layout::from_size_align(100, 32).unwrap()]
+      {
+        let expected = myalloc.fits_count(lay);
+        let mut stored = Vec::new();
+        loop
+        {
+          let ptr = myalloc.alloc(lay);
+          if ptr.is_null()
+          {
+            break;
+          }
+          stored.push(ptr);
+        }
+        assert_eq!(stored.len(), expected, "{:?}", lay);
+        assert_eq!(myalloc.fits_count(lay), 0);
+        for x in stored
+        {
+          myalloc.dealloc(x, lay);
+        }
+      }
+
+      let cell_lay = Layout::from_size_align(48, 16).unwrap();
+      let slab = MetaAlloc::slab(cell_lay.size(), cell_lay.align());
+      let cell = slab.alloc(cell_lay);
+      assert_eq!(slab.fits_count(cell_lay), PAGE_SIZE / cell_lay.size() - 1);
+      assert_eq!(slab.fits_count(LAY), 0);
+      slab.dealloc(cell, cell_lay);
+      assert_eq!(slab.fits_count(cell_lay), PAGE_SIZE / cell_lay.size());
+    }
+  }
+
+  #[test]
+  pub fn min_waste_prefers_aligned_base()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      let body = Layout::from_size_align(368, 16).unwrap();
+
+      // nodes of 16 aligned blocks round up to 48 bytes, so the 80 byte lead puts a on a base 16
+      // past a 64 boundary. a's 416 bytes and LAY's 80 put b right on one
+      let lead_lay = Layout::from_size_align(32, 16).unwrap();
+      let lead = myalloc.alloc(lead_lay);
+      let a = myalloc.alloc(body);
+      let k1 = myalloc.alloc(LAY);
+      let b = myalloc.alloc(body);
+      let k2 = myalloc.alloc(LAY);
+      let block_a = myalloc.metadata_for(a).unwrap();
+      let block_b = myalloc.metadata_for(b).unwrap();
+      assert_ne!(block_a.base.addr().get() % 64, 0);
+      assert_eq!(block_b.base.addr().get() % 64, 0);
+      myalloc.dealloc(a, body);
+      myalloc.dealloc(b, body);
+
+      let wide = Layout::from_size_align(64, 64).unwrap();
+      let first = myalloc.alloc(wide);
+      let meta = myalloc.metadata_for(first).unwrap();
+      assert_eq!(meta.base, block_a.base);
+      assert!(meta.extra_size() > NODE_SIZE.next_multiple_of(64));
+      myalloc.dealloc(first, wide);
+
+      myalloc.set_fit_strategy(FitStrategy::MinWaste);
+      let least = myalloc.alloc(wide);
+      let meta = myalloc.metadata_for(least).unwrap();
+      assert_eq!(meta.base, block_b.base);
+      assert_eq!(meta.extra_size(), NODE_SIZE.next_multiple_of(64));
+      assert!(least.addr().is_multiple_of(64));
+      myalloc.dealloc(least, wide);
+
+      myalloc.dealloc(lead, lead_lay);
+      myalloc.dealloc(k1, LAY);
+      myalloc.dealloc(k2, LAY);
+      myalloc.validate();
+      assert_eq!(myalloc.free_blocks().len(), 1);
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "free list not strictly ordered")]
+  pub fn strict_ordering_catches_equal_bases()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let a = myalloc.alloc(LAY);
+      // stays live so a and c don't merge
+      let _b = myalloc.alloc(LAY);
+      let c = myalloc.alloc(LAY);
+      myalloc.dealloc(a, LAY);
+      myalloc.dealloc(c, LAY);
+      myalloc.assert_strict_ordering();
+
+      // pretend c's block starts where a's does. freeing rewrote both headers as blank ones, which
+      // needn't sit where the data pointers put them, so take the nodes from the free list
+      {
+        let inner = myalloc.tex.lock().unwrap();
+        let node_a = inner.list.peek_front().unwrap();
+        let node_c = (*node_a.as_ptr()).next_node().unwrap();
+        (*node_c.as_ptr()).elem_mut().base = (*node_a.as_ptr()).elem().base;
+      }
+      myalloc.assert_strict_ordering();
+    }
+  }
+
+  #[test]
+  pub fn cache_aligned_blocks()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let mut seen = HashSet::new();
+      let mut stored = Vec::new();
+      for size in [1, 8, 63, 64, 65, 200, 1000]
+      {
+        let ptr = myalloc.alloc_cache_aligned(size);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr.addr() % 64, 0);
+        assert!(seen.insert(ptr.addr()));
+        ptr.write_bytes(0xCA, size);
+        stored.push((ptr, Layout::from_size_align(size, 64).unwrap()));
+      }
+
+      myalloc.set_cache_line(128);
+      let wide = myalloc.alloc_cache_aligned(32);
+      assert_eq!(wide.addr() % 128, 0);
+      assert!(seen.insert(wide.addr()));
+      stored.push((wide, Layout::from_size_align(32, 128).unwrap()));
+
+      for (x, lay) in stored
+      {
+        myalloc.dealloc(x, lay);
+      }
+      assert_eq!(myalloc.stats().bytes_in_use, 0);
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "unhealthy heap")]
+  pub fn assert_healthy_catches_injected_header()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let mut stored = Vec::new();
+      for i in 1..100
+      {
+        let lay = Layout::from_size_align(i * 9, 1 << (i % 5)).unwrap();
+        stored.push((myalloc.alloc(lay), lay));
+      }
+      for (x, lay) in stored.iter().step_by(4)
+      {
+        myalloc.dealloc(*x, *lay);
+      }
+      myalloc.assert_healthy();
+
+      // pretend a live block starts inside its left neighbour
+      let (left, right) = (stored[1].0, stored[2].0);
+      let node_left = raw_to_existing_node(left);
+      let node_right = raw_to_existing_node(right);
+      (*node_right.as_ptr()).elem_mut().base = (*node_left.as_ptr()).elem().base.byte_add(8);
+      myalloc.assert_healthy();
+    }
+  }
+
+  #[test]
+  pub fn many_page_alloc_on_small_stack()
+  {
+    const PAGES: usize = 32;
+    let myalloc = MetaAlloc::new();
+    let lay = Layout::from_size_align(PAGES * PAGE_SIZE, 8).unwrap();
+    // every page of the request is one more pass through alloc, a recursive alloc would need a
+    // frame per page on this stack
+    std::thread::scope(|scope| {
+      std::thread::Builder::new()
+        .stack_size(64 * 1024)
+        .spawn_scoped(scope, || unsafe {
+          let (ptr, committed) = myalloc.alloc_traced(lay);
+          let ptr = ptr.unwrap().as_ptr();
+          assert!(committed);
+          ptr.write_bytes(0x5A, lay.size());
+          myalloc.dealloc(ptr, lay);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    });
+    assert!(myalloc.stats().pages_committed > PAGES);
+    myalloc.validate();
+  }
 
-  use core::alloc::Layout;
-  use std::alloc::GlobalAlloc;
+  #[test]
+  pub fn min_guaranteed_align_for_byte_layouts()
+  {
+    unsafe {
+      let align = MetaAlloc::min_guaranteed_align();
+      assert!(align.is_power_of_two() && align > 1);
 
-  use crate::{MetaAlloc, alloc::PAGE_LAYOUT};
-  const LAY: Layout = unsafe { Layout::from_size_align_unchecked(32, 16) };
+      let myalloc = MetaAlloc::new();
+      let tiny = Layout::from_size_align(3, 1).unwrap();
+      let slab = MetaAlloc::slab(tiny.size(), tiny.align());
+      let mut stored = Vec::new();
+      for n in 1..200
+      {
+        let lay = Layout::from_size_align(n, 1).unwrap();
+        let ptr = myalloc.alloc(lay);
+        assert!(ptr.addr().is_multiple_of(align), "{:p} for {:?}", ptr, lay);
+        stored.push((ptr, lay));
+      }
+      let cells: Vec<_> = (0..50).map(|_| slab.alloc(tiny)).collect();
+      assert!(cells.iter().all(|x| x.addr().is_multiple_of(align)));
+
+      for (x, lay) in stored
+      {
+        myalloc.dealloc(x, lay);
+      }
+      for x in cells
+      {
+        slab.dealloc(x, tiny);
+      }
+    }
+  }
 
   #[test]
-  pub fn fifo_alloc()
+  pub fn poorly_aligned_region_base()
   {
     unsafe {
-      let myalloc: MetaAlloc = MetaAlloc::new();
-      let mut stored: Vec<*mut u8> = Vec::with_capacity(ALLOC_COUNT);
-      for i in 1..ALLOC_COUNT
+      let myalloc = MetaAlloc::with_region_align(8);
+      let mut stored = Vec::new();
+      let first = myalloc.alloc(LAY);
+      stored.push((first, LAY));
+      let heap_base = myalloc.metadata_for(first).unwrap().base;
+      assert_eq!(heap_base.addr().get() % 8, 0);
+      assert_ne!(heap_base.addr().get() % 16, 0);
+
+      for shift in 0..=PAGE_SIZE.trailing_zeros()
       {
-        let a = myalloc.alloc(Layout::from_size_align(i, 8).unwrap());
-        assert!(!a.is_null());
-        assert!(a.is_aligned_to(8));
-        a.write_bytes(0xff, i);
+        let lay = Layout::from_size_align(24, 1 << shift).unwrap();
+        let ptr = myalloc.alloc(lay);
+        assert!(ptr.addr().is_multiple_of(lay.align()), "{:p} for {:?}", ptr, lay);
+        ptr.write_bytes(0x77, lay.size());
+        stored.push((ptr, lay));
+        myalloc.assert_healthy();
+      }
 
-        stored.push(a);
+      for (x, lay) in stored
+      {
+        myalloc.dealloc(x, lay);
       }
-      stored.into_iter().enumerate().for_each(|(i, x)| {
-        myalloc.dealloc(x, Layout::from_size_align(i, 8).unwrap());
-      });
+      assert_eq!(myalloc.stats().bytes_in_use, 0);
+      myalloc.validate();
+      assert_eq!(myalloc.free_blocks()[0].base, heap_base);
     }
   }
 
   #[test]
-  pub fn lifo_alloc()
+  pub fn release_block_returns_pages()
   {
     unsafe {
-      let myalloc: MetaAlloc = MetaAlloc::new();
-      let mut stored = Vec::with_capacity(ALLOC_COUNT);
-      for _ in 0..ALLOC_COUNT
+      let myalloc = MetaAlloc::new();
+      let a = myalloc.alloc(LAY);
+      let whole = Layout::from_size_align(PAGE_SIZE - NODE_SIZE, NODE_ALIGN).unwrap();
+      let b = myalloc.alloc(whole);
+      myalloc.dealloc(b, whole);
+
+      // b's page is free again, either on its own or merged onto the rest of a's page
+      let free = myalloc.free_blocks();
+      let big = free.iter().find(|meta| meta.total_size() >= PAGE_SIZE).unwrap();
+      let small = free.iter().find(|meta| meta.total_size() < PAGE_SIZE);
+      assert!(!myalloc.release_block(NonNull::new(a).unwrap()));
+      if let Some(small) = small
       {
-        let ptr = myalloc.alloc(LAY);
-        assert!(!ptr.is_null());
-        assert!(ptr.is_aligned_to(LAY.align()));
-        stored.push(ptr);
+        assert!(!myalloc.release_block(small.base));
       }
-      stored.into_iter().rev().for_each(|x| {
+
+      let before = myalloc.stats();
+      let committed = myalloc.committed_bytes();
+      assert!(myalloc.release_block(big.base));
+      assert_eq!(myalloc.stats().pages_released, before.pages_released + 1);
+      assert_eq!(myalloc.committed_bytes(), committed - PAGE_SIZE);
+      assert!(myalloc.free_blocks().iter().all(|meta| meta.total_size() < PAGE_SIZE));
+      myalloc.validate();
+
+      let (again, recommitted) = myalloc.alloc_traced(whole);
+      assert!(recommitted);
+      assert_eq!(myalloc.stats().pages_committed, before.pages_committed + 1);
+      myalloc.dealloc(again.unwrap().as_ptr(), whole);
+      myalloc.dealloc(a, LAY);
+    }
+  }
+
+  #[test]
+  pub fn leak_report_lists_call_sites()
+  {
+    let myalloc = MetaAlloc::new();
+    let (a, line_a) = (myalloc.alloc_located(LAY).unwrap(), line!());
+    let (b, line_b) = (myalloc.alloc_located(LAY).unwrap(), line!());
+    let plain = myalloc.alloc_with_id(LAY).unwrap().0;
+
+    let loc_a = myalloc.location_of(a.as_ptr()).unwrap();
+    let loc_b = myalloc.location_of(b.as_ptr()).unwrap();
+    assert_eq!((loc_a.file(), loc_a.line()), (file!(), line_a));
+    assert_eq!((loc_b.file(), loc_b.line()), (file!(), line_b));
+    assert!(myalloc.location_of(plain.as_ptr()).is_none());
+
+    unsafe { myalloc.dealloc(plain.as_ptr(), LAY) };
+    let report = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| myalloc.check_leaks()))
+      .unwrap_err()
+      .downcast::<String>()
+      .unwrap();
+    assert!(report.starts_with("2 allocations leaked"));
+    for line in [line_a, line_b]
+    {
+      assert!(report.contains(&format!("{}:{}:", file!(), line)), "{}", report);
+    }
+
+    unsafe {
+      myalloc.dealloc(a.as_ptr(), LAY);
+      myalloc.dealloc(b.as_ptr(), LAY);
+    }
+    assert!(myalloc.location_of(a.as_ptr()).is_none());
+    myalloc.check_leaks();
+  }
+
+  #[test]
+  pub fn restore_brings_back_call_sites()
+  {
+    let myalloc = MetaAlloc::new();
+    let (a, line_a) = (myalloc.alloc_located(LAY).unwrap(), line!());
+    let cp = myalloc.checkpoint();
+
+    unsafe {
+      myalloc.dealloc(a.as_ptr(), LAY);
+      assert!(myalloc.location_of(a.as_ptr()).is_none());
+      myalloc.restore(cp);
+    }
+
+    // live again, and check_leaks can still tell where it came from
+    let loc_a = myalloc.location_of(a.as_ptr()).unwrap();
+    assert_eq!((loc_a.file(), loc_a.line()), (file!(), line_a));
+    let report = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| myalloc.check_leaks()))
+      .unwrap_err()
+      .downcast::<String>()
+      .unwrap();
+    assert!(
+      report.contains(&format!("{}:{}:", file!(), line_a)),
+      "{}",
+      report
+    );
+
+    unsafe { myalloc.dealloc(a.as_ptr(), LAY) };
+    myalloc.check_leaks();
+  }
+
+  #[test]
+  pub fn reset_to_free_keeps_pages()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      for i in 1..400
+      {
+        let lay = Layout::from_size_align(i * 5, 1 << (i % 6)).unwrap();
+        assert!(!myalloc.alloc(lay).is_null());
+      }
+      let committed = myalloc.committed_bytes();
+      let segments = myalloc.segments();
+      let before = myalloc.stats();
+      assert!(before.pages_committed > 1);
+
+      myalloc.reset_to_free();
+      assert!(myalloc.live_allocations().is_empty());
+      assert_eq!(myalloc.stats().bytes_in_use, 0);
+      assert_eq!(myalloc.committed_bytes(), committed);
+      assert_eq!(myalloc.free_bytes(), committed);
+      assert_eq!(myalloc.segments(), segments);
+      // merged into one free block per run of contiguous pages
+      let blocks: Vec<_> = myalloc
+        .free_blocks()
+        .iter()
+        .map(|meta| (meta.base, meta.total_size()))
+        .collect();
+      assert_eq!(blocks, segments);
+      myalloc.assert_healthy();
+
+      let (ptr, committed_page) = myalloc.alloc_traced(LAY);
+      assert!(!committed_page);
+      assert_eq!(myalloc.stats().pages_committed, before.pages_committed);
+      myalloc.dealloc(ptr.unwrap().as_ptr(), LAY);
+      myalloc.check_leaks();
+    }
+  }
+
+  #[test]
+  pub fn alloc_checked_errors()
+  {
+    let myalloc = MetaAlloc::new();
+    assert_eq!(myalloc.alloc_checked(32, 0), Err(AllocError::BadAlign));
+    assert_eq!(myalloc.alloc_checked(32, 24), Err(AllocError::BadAlign));
+    assert_eq!(
+      myalloc.alloc_checked(isize::MAX as usize, 16),
+      Err(AllocError::BadLayout)
+    );
+    assert_eq!(myalloc.alloc_checked(usize::MAX, 1), Err(AllocError::BadLayout));
+
+    myalloc.set_max_pages(Some(1));
+    assert_eq!(
+      myalloc.alloc_checked(PAGE_SIZE, 8),
+      Err(AllocError::OutOfMemory)
+    );
+    assert_eq!(myalloc.stats().allocations, 0);
+
+    let ptr = myalloc.alloc_checked(100, 32).unwrap();
+    assert!(ptr.as_ptr().addr().is_multiple_of(32));
+    assert_eq!(myalloc.metadata_for(ptr.as_ptr()).unwrap().layout.size(), 100);
+    unsafe { myalloc.dealloc(ptr.as_ptr(), Layout::from_size_align(100, 32).unwrap()) };
+    assert_eq!(myalloc.stats().bytes_in_use, 0);
+  }
+
+  #[test]
+  pub fn wasteful_allocations_reports_slack()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      myalloc.set_max_pages(Some(1));
+      let first = myalloc.alloc(LAY);
+      assert!(myalloc.wasteful_allocations(1).is_empty());
+
+      // leave a free tail of a header plus 56 bytes, 33 of them requested leaves 23 over, too
+      // few for another block
+      let filler = Layout::from_size_align(myalloc.max_allocatable() - NODE_SIZE - 56, 8).unwrap();
+      let filler_ptr = myalloc.alloc(filler);
+      let odd = Layout::from_size_align(33, 1).unwrap();
+      let ptr = myalloc.alloc(odd);
+      assert!(myalloc.free_blocks().is_empty());
+      assert_eq!(myalloc.metadata_for(ptr).unwrap().usable_size(), 56);
+
+      assert_eq!(
+        myalloc.wasteful_allocations(1),
+        [(NonNull::new(ptr).unwrap(), 23)]
+      );
+      assert_eq!(myalloc.wasteful_allocations(23).len(), 1);
+      assert!(myalloc.wasteful_allocations(24).is_empty());
+      assert_eq!(myalloc.wasteful_allocations(0).len(), 3);
+
+      myalloc.dealloc(ptr, odd);
+      assert!(myalloc.wasteful_allocations(1).is_empty());
+      myalloc.dealloc(filler_ptr, filler);
+      myalloc.dealloc(first, LAY);
+    }
+  }
+
+  #[test]
+  pub fn fingerprint_differential()
+  {
+    unsafe {
+      let left = MetaAlloc::new();
+      let right = MetaAlloc::new();
+      assert_eq!(left.fingerprint(), right.fingerprint());
+      // one page each, so the layouts can't differ by how pages happened to line up
+      left.set_max_pages(Some(1));
+      right.set_max_pages(Some(1));
+
+      let mut rng = Rng::new(0xD1FF);
+      let mut live = Vec::new();
+      for _ in 0..300
+      {
+        if live.is_empty() || rng.below(3) != 0
+        {
+          let lay = Layout::from_size_align(rng.below(120) + 1, 1 << rng.below(6)).unwrap();
+          let (l, r) = (left.alloc(lay), right.alloc(lay));
+          assert_eq!(l.is_null(), r.is_null());
+          if !l.is_null()
+          {
+            live.push((l, r, lay));
+          }
+        }
+        else
+        {
+          let (l, r, lay) = live.swap_remove(rng.below(live.len()));
+          left.dealloc(l, lay);
+          right.dealloc(r, lay);
+        }
+        assert_eq!(left.fingerprint(), right.fingerprint());
+      }
+
+      // one free on the left only
+      if live.is_empty()
+      {
+        live.push((left.alloc(LAY), right.alloc(LAY), LAY));
+      }
+      let (l, r, lay) = live.pop().unwrap();
+      left.dealloc(l, lay);
+      assert_ne!(left.fingerprint(), right.fingerprint());
+      right.dealloc(r, lay);
+      assert_eq!(left.fingerprint(), right.fingerprint());
+
+      for (l, r, lay) in live
+      {
+        left.dealloc(l, lay);
+        right.dealloc(r, lay);
+      }
+      assert_eq!(left.fingerprint(), right.fingerprint());
+    }
+  }
+
+  #[test]
+  pub fn bookkeeping_bytes_counts_nodes()
+  {
+    unsafe {
+      const N: usize = 50;
+      let myalloc = MetaAlloc::new();
+      assert_eq!(myalloc.bookkeeping_bytes(), 0);
+
+      let stored: Vec<_> = (0..N).map(|_| myalloc.alloc(LAY)).collect();
+      let plain = myalloc.bookkeeping_bytes();
+      assert!(plain >= N * NODE_SIZE);
+      // the live blocks and their live map entries plus whatever free blocks are left
+      assert_eq!(
+        plain,
+        (N + myalloc.free_blocks().len()) * NODE_SIZE + N * size_of::<(usize, LiveEntry)>()
+      );
+
+      // the same block again with and without a location, only the side table entry differs
+      let located = myalloc.alloc_located(LAY).unwrap();
+      let with_location = myalloc.bookkeeping_bytes();
+      myalloc.dealloc(located.as_ptr(), LAY);
+      assert_eq!(myalloc.bookkeeping_bytes(), plain);
+      let unlocated = myalloc.alloc(LAY);
+      assert_eq!(unlocated, located.as_ptr());
+      assert!(with_location > myalloc.bookkeeping_bytes());
+      myalloc.dealloc(unlocated, LAY);
+
+      for x in stored
+      {
         myalloc.dealloc(x, LAY);
-      });
+      }
+      assert_eq!(
+        myalloc.bookkeeping_bytes(),
+        myalloc.free_blocks().len() * NODE_SIZE
+      );
     }
   }
 
   #[test]
-  pub fn page_alloc()
+  pub fn first_alloc_offset()
   {
     unsafe {
       let myalloc = MetaAlloc::new();
-      let ptr = myalloc.alloc(PAGE_LAYOUT);
-      assert!(!ptr.is_null());
-      myalloc.dealloc(ptr, PAGE_LAYOUT);
+      let lay = Layout::from_size_align(100, 8).unwrap();
+      let ptr = myalloc.alloc(lay);
+      let meta = myalloc.metadata_for(ptr).unwrap();
+
+      // FAKE_HEAP is shared with every other test, so the page index is only stable relative to
+      // the block's own page, which is the one freshly committed page
+      assert!(meta.base.as_ptr().addr().is_multiple_of(PAGE_SIZE));
+      let page = page_index_of(meta.base.as_ptr()).unwrap();
+      assert_eq!(page_index_of(ptr), Some(page));
+      assert_eq!(myalloc.segments(), vec![(meta.base, PAGE_SIZE)]);
+
+      // page aligned base needs no padding, so this is just the node rounded up to the align
+      let offset = ptr.offset_from_unsigned(meta.base.as_ptr());
+      assert_eq!(offset, meta.extra_size());
+      assert_eq!(offset, NODE_SIZE.next_multiple_of(lay.align()));
+      assert_eq!(offset, NODE_SIZE);
+
+      myalloc.dealloc(ptr, lay);
+    }
+  }
+
+  #[test]
+  pub fn heap_bytes_shows_written_pattern()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let lay = Layout::from_size_align(64, 8).unwrap();
+      let ptr = myalloc.alloc(lay);
+      for i in 0..lay.size()
+      {
+        ptr.add(i).write(i as u8 ^ 0xa5);
+      }
+
+      let segments = myalloc.heap_bytes();
+      assert_eq!(segments.len(), myalloc.segments().len());
+      let seg = segments
+        .iter()
+        .find(|seg| (seg.addr()..seg.addr() + seg.len()).contains(&ptr.addr()))
+        .unwrap();
+      let offset = ptr.addr() - seg.addr();
+      assert!(offset + lay.size() <= seg.len());
+
+      // only the block written above, the rest of the segment may never have been written
+      let block = core::slice::from_raw_parts(seg.cast::<u8>().add(offset), lay.size());
+      let expected: Vec<u8> = (0..lay.size()).map(|i| i as u8 ^ 0xa5).collect();
+      assert_eq!(block, expected.as_slice());
+
+      myalloc.dealloc(ptr, lay);
+    }
+  }
+
+  #[test]
+  pub fn lazy_coalesce_waits_for_threshold()
+  {
+    unsafe {
+      const THRESHOLD: usize = 8;
+      let myalloc = MetaAlloc::with_lazy_coalesce(THRESHOLD);
+      let stored: Vec<_> = (0..12).map(|_| myalloc.alloc(LAY)).collect();
+      assert_eq!(myalloc.free_blocks().len(), 1);
+      let before = myalloc.merges_performed();
+
+      // neighbours in address order, each of them would merge onto the one before if eager
+      for (i, &x) in stored[..THRESHOLD - 1].iter().enumerate()
+      {
+        myalloc.dealloc(x, LAY);
+        assert_eq!(myalloc.free_blocks().len(), i + 2);
+      }
+      assert_eq!(myalloc.merges_performed(), before);
+      myalloc.assert_healthy();
+
+      // one over the threshold merges the run of freed blocks, stored[8] keeps it off the tail
+      myalloc.dealloc(stored[THRESHOLD - 1], LAY);
+      assert_eq!(myalloc.merges_performed() - before, THRESHOLD as u64 - 1);
+      assert_eq!(myalloc.free_blocks().len(), 2);
+
+      for &x in &stored[THRESHOLD..]
+      {
+        myalloc.dealloc(x, LAY);
+      }
+      assert_eq!(myalloc.free_blocks().len(), 2 + stored.len() - THRESHOLD);
+      assert!(myalloc.coalesce_free() > 0);
+      assert_eq!(myalloc.free_blocks().len(), 1);
+      assert_eq!(myalloc.stats().bytes_in_use, 0);
+      myalloc.validate();
+    }
+  }
+
+  #[test]
+  pub fn allocation_containing_interior_address()
+  {
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let lay = Layout::from_size_align(100, 8).unwrap();
+      let a = myalloc.alloc(lay);
+      let gap = myalloc.alloc(lay);
+      let b = myalloc.alloc(lay);
+      myalloc.dealloc(gap, lay);
+
+      let expected = Some((NonNull::new(a).unwrap(), lay));
+      assert_eq!(myalloc.allocation_containing(a), expected);
+      assert_eq!(myalloc.allocation_containing(a.add(50)), expected);
+      assert_eq!(myalloc.allocation_containing(a.add(99)), expected);
+      // the header in front of the data isn't part of it
+      assert_eq!(myalloc.allocation_containing(a.sub(1)), None);
+      assert_eq!(myalloc.allocation_containing(gap.add(50)), None);
+      assert_eq!(
+        myalloc.allocation_containing(b.add(10)),
+        Some((NonNull::new(b).unwrap(), lay))
+      );
+
+      myalloc.dealloc(a, lay);
+      assert_eq!(myalloc.allocation_containing(a.add(50)), None);
+      myalloc.dealloc(b, lay);
+    }
+  }
+
+  #[test]
+  pub fn labels_live_beside_the_header()
+  {
+    // ids, seqs, tags and slack are side tables, a header is a base and a layout
+    assert_eq!(
+      size_of::<MetaData>(),
+      size_of::<NonNull<u8>>() + size_of::<Layout>()
+    );
+
+    unsafe {
+      let myalloc = MetaAlloc::new();
+      let plain = myalloc.alloc(LAY);
+      let tagged = myalloc.alloc_tagged(LAY, 7).unwrap();
+      assert_eq!(
+        myalloc.label_of(plain),
+        Some(AllocLabel {
+          id: 0,
+          seq: 0,
+          tag: 0
+        })
+      );
+      assert_eq!(myalloc.label_of(tagged.as_ptr()).unwrap().tag, 7);
+      assert_eq!(myalloc.label_of(tagged.as_ptr()).unwrap().seq, 1);
+
+      let cp = myalloc.checkpoint();
+      myalloc.dealloc(tagged.as_ptr(), LAY);
+      assert_eq!(myalloc.label_of(tagged.as_ptr()), None);
+      myalloc.restore(cp);
+      assert_eq!(myalloc.label_of(tagged.as_ptr()).unwrap().tag, 7);
+
+      myalloc.dealloc(plain, LAY);
+      myalloc.dealloc(tagged.as_ptr(), LAY);
+      assert_eq!(
+        myalloc.bookkeeping_bytes(),
+        myalloc.free_blocks().len() * NODE_SIZE
+      );
+    }
+  }
+
+  // the free list as a sorted Vec of headers, the obvious version of every FreeList operation to
+  // hold List<MetaData> against
+  struct VecFreeList(Vec<NonNull<Node<MetaData>>>);
+
+  unsafe fn meta_of<'a>(node: NonNull<Node<MetaData>>) -> &'a mut MetaData
+  {
+    unsafe { (*node.as_ptr()).elem_mut() }
+  }
+
+  // whether b starts right where a ends, a then absorbs b
+  unsafe fn absorb(a: NonNull<Node<MetaData>>, b: NonNull<Node<MetaData>>) -> bool
+  {
+    let (a, b) = unsafe { (meta_of(a), meta_of(b)) };
+    if a.base.addr().get() + a.total_size() != b.base.addr().get()
+    {
+      return false;
+    }
+    a.layout = Layout::from_size_align(a.layout.size() + b.total_size(), a.layout.align()).unwrap();
+    true
+  }
+
+  impl FreeList for VecFreeList
+  {
+    fn insert_sorted(&mut self, node: NonNull<Node<MetaData>>, merge: bool) -> usize
+    {
+      let base = unsafe { meta_of(node).base };
+      let at = self
+        .0
+        .partition_point(|x| unsafe { meta_of(*x).base } < base);
+      self.0.insert(at, node);
+      if !merge
+      {
+        return 0;
+      }
+
+      let mut merges = 0;
+      if at + 1 < self.0.len() && unsafe { absorb(self.0[at], self.0[at + 1]) }
+      {
+        self.0.remove(at + 1);
+        merges += 1;
+      }
+      if at > 0 && unsafe { absorb(self.0[at - 1], self.0[at]) }
+      {
+        self.0.remove(at);
+        merges += 1;
+      }
+      merges
+    }
+
+    fn remove(&mut self, mut pick: impl FnMut(&MetaData) -> bool)
+    -> Option<NonNull<Node<MetaData>>>
+    {
+      let at = self.0.iter().position(|x| pick(unsafe { meta_of(*x) }))?;
+      Some(self.0.remove(at))
+    }
+
+    fn iterate(&self) -> impl Iterator<Item = NonNull<Node<MetaData>>>
+    {
+      self.0.iter().copied()
+    }
+
+    fn merge_neighbors(&mut self) -> (usize, usize)
+    {
+      let (mut merges, mut absorbed) = (0, 0);
+      let mut i = 0;
+      while i + 1 < self.0.len()
+      {
+        let right_size = unsafe { meta_of(self.0[i + 1]).total_size() };
+        if unsafe { absorb(self.0[i], self.0[i + 1]) }
+        {
+          self.0.remove(i + 1);
+          merges += 1;
+          absorbed += right_size;
+        }
+        else
+        {
+          i += 1;
+        }
+      }
+      (merges, absorbed)
+    }
+
+    fn replace(&mut self, sorted: impl IntoIterator<Item = NonNull<Node<MetaData>>>)
+    {
+      self.0 = sorted.into_iter().collect();
+    }
+  }
+
+  // (offset from base, total size) of every block on list
+  fn free_list_shape(list: &impl FreeList, base: usize) -> Vec<(usize, usize)>
+  {
+    list
+      .iterate()
+      .map(|node| {
+        let meta = unsafe { meta_of(node) };
+        (meta.base.addr().get() - base, meta.total_size())
+      })
+      .collect()
+  }
+
+  #[test]
+  pub fn free_list_matches_reference()
+  {
+    // the same blocks carved out of two buffers, one per list, so the nodes can't be shared
+    let mut bufs = [vec![0u64; PAGE_SIZE / 8], vec![0u64; PAGE_SIZE / 8]];
+    let bases = bufs
+      .each_mut()
+      .map(|buf| NonNull::new(buf.as_mut_ptr().cast::<u8>()).unwrap());
+    let mut rng = Rng::new(194);
+
+    let mut sizes = Vec::new();
+    let mut used = 0;
+    while PAGE_SIZE - used >= 2 * 256
+    {
+      let size = (NODE_SIZE + 8 + rng.below(200)).next_multiple_of(NODE_ALIGN);
+      sizes.push(size);
+      used += size;
+    }
+    sizes.push(PAGE_SIZE - used);
+
+    let carve = |base: NonNull<u8>| {
+      let mut offset = 0;
+      let mut ret = Vec::new();
+      for size in &sizes
+      {
+        let block = unsafe { base.byte_add(offset) };
+        ret.push(meta_write(MetaData::new_blank(block, *size).unwrap()));
+        offset += size;
+      }
+      ret
+    };
+    let mut outside: Vec<_> = carve(bases[0]).into_iter().zip(carve(bases[1])).collect();
+
+    let mut list = List::new();
+    let mut reference = VecFreeList(Vec::new());
+    let addrs = bases.map(|base| base.addr().get());
+    for _ in 0..2000
+    {
+      match rng.below(8)
+      {
+        0..4 if !outside.is_empty() =>
+        {
+          let (a, b) = outside.swap_remove(rng.below(outside.len()));
+          let merge = rng.below(4) != 0;
+          assert_eq!(
+            list.insert_sorted(a, merge),
+            reference.insert_sorted(b, merge)
+          );
+        }
+        0..7 =>
+        {
+          let min = rng.below(PAGE_SIZE / 4);
+          let a = list.remove(|meta| meta.total_size() >= min);
+          let b = reference.remove(|meta| meta.total_size() >= min);
+          assert_eq!(
+            a.map(|a| unsafe { meta_of(a).base.addr().get() } - addrs[0]),
+            b.map(|b| unsafe { meta_of(b).base.addr().get() } - addrs[1])
+          );
+          if let (Some(a), Some(b)) = (a, b)
+          {
+            outside.push((a, b));
+          }
+        }
+        _ => assert_eq!(list.merge_neighbors(), reference.merge_neighbors()),
+      }
+      assert_eq!(
+        free_list_shape(&list, addrs[0]),
+        free_list_shape(&reference, addrs[1])
+      );
     }
+
+    // everything back in and merged is the whole buffer again
+    for (a, b) in outside
+    {
+      list.insert_sorted(a, true);
+      reference.insert_sorted(b, true);
+    }
+    list.merge_neighbors();
+    reference.merge_neighbors();
+    assert_eq!(free_list_shape(&list, addrs[0]), [(0, PAGE_SIZE)]);
+    assert_eq!(free_list_shape(&reference, addrs[1]), [(0, PAGE_SIZE)]);
+
+    list.replace(core::iter::empty());
+    assert_eq!(list.iterate().count(), 0);
   }
 
   #[test]
@@ -0,0 +1,87 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+use crate::MetaAlloc;
+
+// owns one T living in a MetaAlloc block, drops the T and then frees the block
+pub struct AllocBox<'a, T>
+{
+  alloc: &'a MetaAlloc,
+  ptr: NonNull<T>,
+  _owns: PhantomData<T>,
+}
+
+impl MetaAlloc
+{
+  // None when the allocator is out of pages, value is dropped in that case
+  pub fn alloc_boxed<T>(&self, value: T) -> Option<AllocBox<'_, T>>
+  {
+    let ptr = NonNull::new(unsafe { self.alloc(Layout::new::<T>()) })?.cast::<T>();
+    unsafe { ptr.write(value) };
+
+    Some(AllocBox {
+      alloc: self,
+      ptr,
+      _owns: PhantomData,
+    })
+  }
+}
+
+impl<T> Deref for AllocBox<'_, T>
+{
+  type Target = T;
+
+  fn deref(&self) -> &T
+  {
+    unsafe { self.ptr.as_ref() }
+  }
+}
+
+impl<T> DerefMut for AllocBox<'_, T>
+{
+  fn deref_mut(&mut self) -> &mut T
+  {
+    unsafe { self.ptr.as_mut() }
+  }
+}
+
+impl<T> Drop for AllocBox<'_, T>
+{
+  fn drop(&mut self)
+  {
+    unsafe {
+      self.ptr.drop_in_place();
+      self
+        .alloc
+        .dealloc(self.ptr.cast().as_ptr(), Layout::new::<T>());
+    }
+  }
+}
+
+#[cfg(test)]
+mod alloc_box_tests
+{
+  use crate::MetaAlloc;
+
+  #[test]
+  pub fn boxed_string()
+  {
+    let myalloc = MetaAlloc::new();
+    {
+      let mut boxed = myalloc.alloc_boxed(String::from("hello")).unwrap();
+      boxed.push_str(", world");
+      assert_eq!(boxed.as_str(), "hello, world");
+
+      let stats = myalloc.stats();
+      assert_eq!(stats.allocations, 1);
+      assert_eq!(stats.deallocations, 0);
+    }
+
+    let stats = myalloc.stats();
+    assert_eq!(stats.deallocations, 1);
+    assert_eq!(stats.bytes_in_use, 0);
+    myalloc.validate();
+  }
+}
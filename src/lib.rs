@@ -1,2 +1,8 @@
 mod alloc;
+mod alloc_box;
+mod pool;
+mod workload;
 pub use alloc::*;
+pub use alloc_box::*;
+pub use pool::*;
+pub use workload::*;
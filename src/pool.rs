@@ -0,0 +1,145 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+use crate::MetaAlloc;
+
+// fixed set of same layout blocks allocated up front, acquire and release only push and pop a
+// stack so benchmarks can recycle pointers without measuring the allocator. every block goes back
+// to the allocator when the pool is dropped, acquired or not
+pub struct PointerPool<'a>
+{
+  alloc: &'a MetaAlloc,
+  layout: Layout,
+  blocks: Vec<NonNull<u8>>,
+  free: Vec<NonNull<u8>>,
+}
+
+impl MetaAlloc
+{
+  // panics when the allocator runs out of pages before count blocks are allocated
+  pub fn pooled(&self, layout: Layout, count: usize) -> PointerPool<'_>
+  {
+    let blocks: Vec<NonNull<u8>> = (0..count)
+      .map(|i| {
+        NonNull::new(unsafe { self.alloc(layout) })
+          .unwrap_or_else(|| panic!("pool of {} {:?} ran out of memory at {}", count, layout, i))
+      })
+      .collect();
+
+    PointerPool {
+      alloc: self,
+      layout,
+      free: blocks.clone(),
+      blocks,
+    }
+  }
+}
+
+impl PointerPool<'_>
+{
+  // the most recently released block, None once every block is out
+  pub fn acquire(&mut self) -> Option<NonNull<u8>>
+  {
+    self.free.pop()
+  }
+
+  pub fn release(&mut self, ptr: NonNull<u8>)
+  {
+    debug_assert!(self.free.len() < self.blocks.len(), "released more blocks than the pool holds");
+    self.free.push(ptr);
+  }
+
+  pub fn layout(&self) -> Layout
+  {
+    self.layout
+  }
+
+  // blocks that can be acquired right now
+  pub fn available(&self) -> usize
+  {
+    self.free.len()
+  }
+}
+
+impl Drop for PointerPool<'_>
+{
+  fn drop(&mut self)
+  {
+    for ptr in &self.blocks
+    {
+      unsafe { self.alloc.dealloc(ptr.as_ptr(), self.layout) };
+    }
+  }
+}
+
+#[cfg(test)]
+mod pool_tests
+{
+  use core::alloc::Layout;
+
+  use crate::MetaAlloc;
+
+  #[test]
+  pub fn pool_recycles_pointers()
+  {
+    let myalloc = MetaAlloc::new();
+    let lay = Layout::from_size_align(48, 16).unwrap();
+    {
+      let mut pool = myalloc.pooled(lay, 8);
+      let before = myalloc.stats();
+      assert_eq!(before.allocations, 8);
+      assert_eq!(pool.available(), 8);
+      let mut all: Vec<_> = core::iter::from_fn(|| pool.acquire()).collect();
+      for ptr in &all
+      {
+        pool.release(*ptr);
+      }
+      all.sort();
+
+      let mut held = Vec::new();
+      for round in 0..100
+      {
+        let ptr = pool.acquire().unwrap();
+        assert!(ptr.as_ptr().addr().is_multiple_of(lay.align()));
+        if round % 3 == 0
+        {
+          held.push(ptr);
+        }
+        else
+        {
+          pool.release(ptr);
+          // lifo, the block just released is the next one handed out
+          assert_eq!(pool.acquire(), Some(ptr));
+          pool.release(ptr);
+        }
+        if held.len() == 4
+        {
+          for ptr in held.drain(..)
+          {
+            pool.release(ptr);
+          }
+        }
+      }
+      // 100 rounds leave a partial batch held
+      for ptr in held.drain(..)
+      {
+        pool.release(ptr);
+      }
+
+      let mut drained: Vec<_> = core::iter::from_fn(|| pool.acquire()).collect();
+      drained.sort();
+      assert_eq!(drained, all);
+      assert!(all.iter().all(|ptr| myalloc.owns(ptr.as_ptr())));
+      assert_eq!(pool.acquire(), None);
+
+      let after = myalloc.stats();
+      assert_eq!(after.allocations, before.allocations);
+      assert_eq!(after.deallocations, before.deallocations);
+    }
+
+    let stats = myalloc.stats();
+    assert_eq!(stats.deallocations, 8);
+    assert_eq!(stats.bytes_in_use, 0);
+    myalloc.validate();
+  }
+}
@@ -0,0 +1,256 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+use crate::MetaAlloc;
+
+// how many operations run between two `assert_healthy` calls
+const VALIDATE_INTERVAL: usize = 64;
+const MAX_SIZE: usize = 512;
+// alignments from 1 up to 1 << MAX_ALIGN_SHIFT
+const MAX_ALIGN_SHIFT: u32 = 6;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkloadSummary
+{
+  pub allocs: usize,
+  pub frees: usize,
+  pub reallocs: usize,
+
+  // allocations or reallocs that returned null
+  pub failures: usize,
+}
+
+// xorshift64*, good enough to shuffle operations and stays reproducible per seed
+pub(crate) struct Rng(u64);
+
+impl Rng
+{
+  pub(crate) fn new(seed: u64) -> Self
+  {
+    // xorshift never leaves the all zero state
+    Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+  }
+
+  pub(crate) fn next(&mut self) -> u64
+  {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+
+  pub(crate) fn below(&mut self, bound: usize) -> usize
+  {
+    (self.next() % bound as u64) as usize
+  }
+}
+
+struct Live
+{
+  ptr: *mut u8,
+  layout: Layout,
+  fill: u8,
+}
+
+unsafe fn check_fill(live: &Live, len: usize)
+{
+  let bytes = unsafe { core::slice::from_raw_parts(live.ptr, len) };
+  if let Some(offset) = bytes.iter().position(|b| *b != live.fill)
+  {
+    panic!(
+      "allocation {:p} ({:?}) corrupted at offset {}: expected {:#x}, found {:#x}",
+      live.ptr, live.layout, offset, live.fill, bytes[offset]
+    );
+  }
+}
+
+// runs `ops` pseudo random allocs, frees and reallocs against `alloc`, every live allocation
+// carries its own fill byte that is checked before it is freed or moved. everything still live
+// at the end is freed, so a correct allocator ends with nothing in use
+pub fn run_random_workload(alloc: &MetaAlloc, seed: u64, ops: usize) -> WorkloadSummary
+{
+  let mut rng = Rng::new(seed);
+  let mut summary = WorkloadSummary::default();
+  let mut live: Vec<Live> = Vec::new();
+
+  for op in 0..ops
+  {
+    let choice = if live.is_empty() { 0 } else { rng.below(3) };
+    match choice
+    {
+      0 =>
+      {
+        let size = rng.below(MAX_SIZE) + 1;
+        let align = 1 << rng.below(MAX_ALIGN_SHIFT as usize + 1);
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let fill = rng.next() as u8;
+
+        let ptr = unsafe { alloc.alloc(layout) };
+        if ptr.is_null()
+        {
+          summary.failures += 1;
+          continue;
+        }
+        assert!(
+          ptr.addr().is_multiple_of(align),
+          "allocation {:p} not aligned to {}",
+          ptr,
+          align
+        );
+        unsafe { ptr.write_bytes(fill, size) };
+        live.push(Live { ptr, layout, fill });
+        summary.allocs += 1;
+      }
+      1 =>
+      {
+        let entry = live.swap_remove(rng.below(live.len()));
+        unsafe {
+          check_fill(&entry, entry.layout.size());
+          alloc.dealloc(entry.ptr, entry.layout);
+        }
+        summary.frees += 1;
+      }
+      _ =>
+      {
+        let index = rng.below(live.len());
+        let new_size = rng.below(MAX_SIZE) + 1;
+        let entry = &mut live[index];
+
+        unsafe {
+          check_fill(entry, entry.layout.size());
+          let ptr = alloc.realloc(entry.ptr, entry.layout, new_size);
+          if ptr.is_null()
+          {
+            summary.failures += 1;
+            continue;
+          }
+
+          let kept = entry.layout.size().min(new_size);
+          entry.ptr = ptr;
+          entry.layout = Layout::from_size_align(new_size, entry.layout.align()).unwrap();
+          check_fill(entry, kept);
+          ptr.write_bytes(entry.fill, new_size);
+        }
+        summary.reallocs += 1;
+      }
+    }
+
+    if op.is_multiple_of(VALIDATE_INTERVAL)
+    {
+      alloc.assert_healthy();
+    }
+  }
+
+  for entry in live
+  {
+    unsafe {
+      check_fill(&entry, entry.layout.size());
+      alloc.dealloc(entry.ptr, entry.layout);
+    }
+    summary.frees += 1;
+  }
+  alloc.validate();
+
+  summary
+}
+
+#[cfg(test)]
+mod workload_tests
+{
+  use core::alloc::{GlobalAlloc, Layout};
+
+  use super::run_random_workload;
+  use crate::MetaAlloc;
+
+  #[test]
+  pub fn random_workload_seeds()
+  {
+    for seed in [0, 1, 42, 0xDEAD_BEEF, u64::MAX]
+    {
+      let myalloc = MetaAlloc::new();
+      let summary = run_random_workload(&myalloc, seed, 2000);
+      let stats = myalloc.stats();
+
+      assert_eq!(summary.failures, 0);
+      assert_eq!(summary.allocs, summary.frees);
+      assert!(summary.reallocs > 0);
+      assert_eq!(stats.bytes_in_use, 0);
+      assert_eq!(stats.allocations, stats.deallocations);
+    }
+  }
+
+  // leans on the validate MetaAlloc runs after every dealloc and split in debug builds
+  #[test]
+  #[cfg(debug_assertions)]
+  pub fn long_workload_debug_invariants()
+  {
+    let myalloc = MetaAlloc::new();
+    let summary = run_random_workload(&myalloc, 0xD3B6, 20_000);
+    assert_eq!(summary.failures, 0);
+    assert_eq!(myalloc.stats().bytes_in_use, 0);
+  }
+
+  #[test]
+  pub fn workload_keeps_free_list_strictly_ordered()
+  {
+    let myalloc = MetaAlloc::new();
+    for seed in [5, 6, 7]
+    {
+      run_random_workload(&myalloc, seed, 1500);
+      myalloc.assert_strict_ordering();
+    }
+
+    // leave every other block of a batch live so the free list has more than one block to order
+    let mut stored = Vec::new();
+    for i in 1..300
+    {
+      let lay = Layout::from_size_align(i % 97 + 1, 1 << (i % 6)).unwrap();
+      stored.push((unsafe { myalloc.alloc(lay) }, lay));
+    }
+    for (x, lay) in stored.iter().step_by(2)
+    {
+      unsafe { myalloc.dealloc(*x, *lay) };
+    }
+    assert!(myalloc.free_blocks().len() > 1);
+    myalloc.assert_strict_ordering();
+
+    for (x, lay) in stored.iter().skip(1).step_by(2)
+    {
+      unsafe { myalloc.dealloc(*x, *lay) };
+    }
+    myalloc.assert_strict_ordering();
+  }
+
+  // the workload itself runs assert_healthy every VALIDATE_INTERVAL ops
+  #[test]
+  pub fn long_workload_stays_healthy()
+  {
+    let myalloc = MetaAlloc::new();
+    for seed in [11, 12, 13, 14]
+    {
+      let summary = run_random_workload(&myalloc, seed, 5000);
+      assert_eq!(summary.failures, 0);
+      myalloc.assert_healthy();
+    }
+    assert_eq!(myalloc.stats().bytes_in_use, 0);
+  }
+
+  #[test]
+  pub fn snapshot_delta_matches_workload()
+  {
+    let myalloc = MetaAlloc::new();
+    run_random_workload(&myalloc, 3, 500);
+
+    let before = myalloc.snapshot();
+    let summary = run_random_workload(&myalloc, 4, 1000);
+    let delta = myalloc.snapshot().delta(&before);
+
+    // GlobalAlloc's default realloc is an alloc plus a dealloc
+    assert_eq!(delta.stats.allocations, summary.allocs + summary.reallocs);
+    assert_eq!(delta.stats.deallocations, summary.frees + summary.reallocs);
+    assert_eq!(delta.stats.bytes_in_use, 0);
+    assert_eq!(
+      delta.align_counts.iter().sum::<usize>(),
+      delta.stats.allocations
+    );
+  }
+}